@@ -0,0 +1,94 @@
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, NativeCallContext, Position};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+/// What integer `+`, `-` and `*` do when the result does not fit in an `i64`.
+///
+/// Set server-wide (`overflow` in the config) or per script with `//! overflow: <policy>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// Abort the script; the caller gets a 422 `overflow` error. Rhai's own behaviour.
+    #[default]
+    Reject,
+    /// Clamp to `i64::MIN` / `i64::MAX`.
+    Saturate,
+    /// Carry on in decimal, which holds 96-bit integers; the result type becomes `decimal`.
+    /// Overflowing that too is rejected as above.
+    Promote,
+}
+
+impl Overflow {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "reject" => Overflow::Reject,
+            "saturate" => Overflow::Saturate,
+            "promote" => Overflow::Promote,
+            _ => return None,
+        })
+    }
+}
+
+/// `(operator, op-assignment, name, checked, saturating, decimal)` for each overridden
+/// operator; the name goes into the overflow error, worded as Rhai's own.
+#[allow(clippy::type_complexity)]
+const OPERATORS: [(
+    &str,
+    &str,
+    &str,
+    fn(i64, i64) -> Option<i64>,
+    fn(i64, i64) -> i64,
+    fn(Decimal, Decimal) -> Option<Decimal>,
+); 3] = [
+    ("+", "+=", "Addition", i64::checked_add, i64::saturating_add, Decimal::checked_add),
+    ("-", "-=", "Subtraction", i64::checked_sub, i64::saturating_sub, Decimal::checked_sub),
+    ("*", "*=", "Multiplication", i64::checked_mul, i64::saturating_mul, Decimal::checked_mul),
+];
+
+/// Overrides the integer operators on `engine` according to `policy`.
+pub fn register(engine: &mut Engine, policy: Overflow) {
+    match policy {
+        Overflow::Reject => {} // Rhai already checks integer arithmetic
+        Overflow::Saturate => {
+            // Built-in operators would otherwise run before any registered override.
+            engine.set_fast_operators(false);
+            for (op, op_assign, _, _, saturating, _) in OPERATORS {
+                engine.register_fn(op, saturating);
+                engine.register_fn(op_assign, move |a: &mut i64, b: i64| *a = saturating(*a, b));
+            }
+        }
+        Overflow::Promote => {
+            engine.set_fast_operators(false);
+            for (op, op_assign, name, checked, _, decimal) in OPERATORS {
+                let promote = move |a: i64, b: i64| -> Result<Dynamic, Box<EvalAltResult>> {
+                    if let Some(value) = checked(a, b) {
+                        return Ok(Dynamic::from_int(value));
+                    }
+                    match decimal(a.into(), b.into()) {
+                        Some(value) => Ok(Dynamic::from_decimal(value)),
+                        None => Err(EvalAltResult::ErrorArithmetic(format!("{name} overflow: {a} {op} {b}"), Position::NONE).into()),
+                    }
+                };
+                engine.register_fn(op, promote);
+                // `x += y` has to be able to change the type of `x` from int to decimal, so
+                // this takes any left-hand side; those other than an integer get what the
+                // built-in operator would have done.
+                engine.register_fn(
+                    op_assign,
+                    move |ctx: NativeCallContext, a: &mut Dynamic, b: i64| -> Result<(), Box<EvalAltResult>> {
+                        if let Ok(int) = a.as_int() {
+                            *a = promote(int, b)?;
+                        } else if op_assign == "+=" && a.is::<Array>() {
+                            a.write_lock::<Array>().unwrap().push(b.into());
+                        } else if op_assign == "+=" && a.is::<Blob>() {
+                            a.write_lock::<Blob>().unwrap().push(b as u8);
+                        } else {
+                            *a = ctx.call_native_fn::<Dynamic>(op, (a.clone(), b))?;
+                        }
+                        Ok(())
+                    },
+                );
+            }
+        }
+    }
+}
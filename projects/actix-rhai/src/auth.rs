@@ -0,0 +1,351 @@
+use crate::error::{ErrorKind, ScriptError};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web::Data,
+    HttpMessage, HttpRequest,
+};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// The keys file: every set of credentials that may use the server.
+///
+/// ```json
+/// {
+///     "keys": [
+///         { "name": "ci", "key": "0c5f2e...", "scripts": ["add", "multiply"],
+///           "rate": { "per_second": 5, "burst": 10 } },
+///         { "name": "ops", "username": "ops", "password": "...", "scripts": ["*"], "admin": true }
+///     ]
+/// }
+/// ```
+///
+/// A key is sent as `Authorization: Bearer <key>` or `X-API-Key: <key>`, a username and
+/// password as basic auth. `"*"` allows every script; only `admin` credentials may use
+/// `/admin/*` and `/repl`. Without `rate` a key is not rate limited.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    name: String,
+    key: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    #[serde(default)]
+    scripts: Vec<String>,
+    #[serde(default)]
+    admin: bool,
+    rate: Option<Rate>,
+}
+
+/// Token bucket settings: `burst` requests at once, refilled at `per_second`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rate {
+    per_second: f64,
+    burst: f64,
+}
+
+/// One set of credentials from the keys file and what it may do.
+pub struct ApiKey {
+    name: String,
+    scripts: Vec<String>,
+    admin: bool,
+    bucket: Option<Mutex<Bucket>>,
+}
+
+impl ApiKey {
+    fn may_call(&self, script: &str) -> bool {
+        self.scripts.iter().any(|allowed| allowed == "*" || allowed == script)
+    }
+}
+
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Takes a token, or says in how many seconds the next one will be there.
+    fn take(&mut self) -> Result<(), u64> {
+        let now = Instant::now();
+        let refill = now.duration_since(self.updated).as_secs_f64() * self.rate.per_second;
+        self.tokens = (self.tokens + refill).min(self.rate.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = (1.0 - self.tokens) / self.rate.per_second;
+        Err((wait.ceil() as u64).max(1))
+    }
+}
+
+/// Credentials loaded from the keys file, plus where to record what they did.
+pub struct Auth {
+    keys: HashMap<String, Arc<ApiKey>>,             // API key -> credentials
+    logins: HashMap<String, (String, Arc<ApiKey>)>, // username -> (password, credentials)
+    audit: Arc<AuditLog>,
+}
+
+impl Auth {
+    /// Reads and checks the keys file; the audit trail goes to `audit_log`, or to stdout
+    /// if that is `None`.
+    pub fn load(keys_file: &Path, audit_log: Option<&Path>) -> Result<Auth, String> {
+        let text = std::fs::read_to_string(keys_file)
+            .map_err(|err| format!("cannot read keys file {}: {err}", keys_file.display()))?;
+        let file: KeysFile =
+            serde_json::from_str(&text).map_err(|err| format!("keys file {}: {err}", keys_file.display()))?;
+
+        let mut auth = Auth {
+            keys: HashMap::new(),
+            logins: HashMap::new(),
+            audit: Arc::new(AuditLog::open(audit_log)?),
+        };
+        let mut names = Vec::new();
+        for entry in file.keys {
+            let problem = |problem: &str| format!("keys file {}: key `{}`: {problem}", keys_file.display(), entry.name);
+            if names.contains(&entry.name) {
+                return Err(problem("the name is used more than once"));
+            }
+            names.push(entry.name.clone());
+            if let Some(rate) = entry.rate
+                && !(rate.per_second > 0.0 && rate.burst >= 1.0)
+            {
+                return Err(problem("`rate` needs `per_second` above 0 and `burst` of at least 1"));
+            }
+
+            let key = Arc::new(ApiKey {
+                name: entry.name.clone(),
+                scripts: entry.scripts,
+                admin: entry.admin,
+                bucket: entry.rate.map(|rate| {
+                    Mutex::new(Bucket {
+                        rate,
+                        tokens: rate.burst,
+                        updated: Instant::now(),
+                    })
+                }),
+            });
+            match (entry.key, entry.username, entry.password) {
+                (Some(secret), None, None) if !secret.is_empty() => {
+                    if auth.keys.insert(secret, key).is_some() {
+                        return Err(problem("the same key is listed twice"));
+                    }
+                }
+                (None, Some(username), Some(password)) if !password.is_empty() => {
+                    if auth.logins.insert(username, (password, key)).is_some() {
+                        return Err(problem("the same username is listed twice"));
+                    }
+                }
+                _ => return Err(problem("needs either a non-empty `key` or a `username` and `password`")),
+            }
+        }
+        Ok(auth)
+    }
+
+    fn authenticate(&self, req: &ServiceRequest) -> Result<Arc<ApiKey>, ScriptError> {
+        let unauthorized = |message: &str| ScriptError::new(ErrorKind::Unauthorized, message);
+        let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+
+        if let Some(secret) = header(header::HeaderName::from_static("x-api-key")) {
+            return self.keys.get(secret).cloned().ok_or_else(|| unauthorized("unknown API key"));
+        }
+        let Some(authorization) = header(header::AUTHORIZATION) else {
+            return Err(unauthorized("credentials required"));
+        };
+        if let Some(secret) = authorization.strip_prefix("Bearer ") {
+            return self.keys.get(secret.trim()).cloned().ok_or_else(|| unauthorized("unknown API key"));
+        }
+        if let Some(encoded) = authorization.strip_prefix("Basic ") {
+            let decoded = BASE64_STANDARD.decode(encoded.trim()).ok().and_then(|bytes| String::from_utf8(bytes).ok());
+            let Some((username, password)) = decoded.as_deref().and_then(|login| login.split_once(':')) else {
+                return Err(unauthorized("malformed basic auth credentials"));
+            };
+            return match self.logins.get(username) {
+                Some((expected, key)) if same(expected, password) => Ok(key.clone()),
+                _ => Err(unauthorized("unknown username or wrong password")),
+            };
+        }
+        Err(unauthorized("unsupported authorization scheme (use Bearer or Basic)"))
+    }
+}
+
+/// Compares without stopping at the first difference, so the time taken says nothing
+/// about how much of a password was right.
+fn same(expected: &str, given: &str) -> bool {
+    expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Append-only record of which credentials ran which script, one JSON object per line.
+pub struct AuditLog {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AuditLog {
+    fn open(path: Option<&Path>) -> Result<Self, String> {
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| format!("cannot open audit log {}: {err}", path.display()))?,
+            ),
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(AuditLog { out: Mutex::new(out) })
+    }
+
+    fn record(&self, key: &str, script: &str, outcome: &str) {
+        let time_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let line = json!({ "time_ms": time_ms, "key": key, "script": script, "outcome": outcome });
+        let mut out = self.out.lock().unwrap();
+        if let Err(err) = writeln!(out, "{line}").and_then(|_| out.flush()) {
+            error!(error = %err, "cannot write audit log");
+        }
+    }
+}
+
+/// The authenticated caller of a request, stored in its extensions by [`check`].
+#[derive(Clone)]
+pub struct Caller {
+    key: Arc<ApiKey>,
+    audit: Arc<AuditLog>,
+}
+
+impl Caller {
+    /// The caller of `req`; `None` when authentication is turned off.
+    pub fn of(req: &HttpRequest) -> Option<Caller> {
+        req.extensions().get::<Caller>().cloned()
+    }
+
+    /// Checks that the caller may run `script`; refusals are audited too.
+    pub fn authorize(&self, script: &str) -> Result<(), ScriptError> {
+        if self.key.may_call(script) {
+            return Ok(());
+        }
+        self.audit.record(&self.key.name, script, "forbidden");
+        Err(ScriptError::new(
+            ErrorKind::Forbidden,
+            format!("`{}` may not run `{script}`", self.key.name),
+        ))
+    }
+
+    /// Records that the caller ran `script` and how that went: `error` is `None` if it
+    /// succeeded.
+    pub fn audit(&self, script: &str, error: Option<&ScriptError>) {
+        let outcome = match error {
+            None => "ok".to_string(),
+            Some(err) => err.kind.to_string(),
+        };
+        self.audit.record(&self.key.name, script, &outcome);
+    }
+}
+
+/// Middleware rejecting requests without valid credentials (401), admin routes for
+/// credentials that aren't admin (403), and requests over the caller's rate (429).
+/// The health checks stay open so an orchestrator can probe them without a key.
+///
+/// Paths are checked as the router sees them, percent-decoded, so `/%61dmin/state` is
+/// as much an admin route as `/admin/state`.
+///
+/// Rejections are answered right here, so outer middleware sees them as responses.
+/// Which scripts a caller may run is checked where the script is known, through
+/// [`Caller::authorize`].
+pub async fn check(
+    auth: Data<Auth>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if matches!(req.match_info().as_str(), "/healthz" | "/readyz") {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let key = match admit(&auth, &req) {
+        Ok(key) => key,
+        Err(err) => return Ok(req.error_response(err).map_into_right_body()),
+    };
+    req.extensions_mut().insert(Caller {
+        key,
+        audit: auth.audit.clone(),
+    });
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// The credentials of `req`, if they may make it.
+fn admit(auth: &Auth, req: &ServiceRequest) -> Result<Arc<ApiKey>, ScriptError> {
+    let key = auth.authenticate(req)?;
+    let path = req.match_info().as_str();
+    if (path.starts_with("/admin/") || path == "/repl") && !key.admin {
+        return Err(ScriptError::new(ErrorKind::Forbidden, format!("`{}` may not use {path}", key.name)));
+    }
+    if let Some(bucket) = &key.bucket
+        && let Err(seconds) = bucket.lock().unwrap().take()
+    {
+        return Err(ScriptError {
+            retry_after: Some(seconds),
+            ..ScriptError::new(ErrorKind::RateLimited, format!("rate limit of `{}` exceeded", key.name))
+        });
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware::from_fn, test, web, App, HttpResponse};
+
+    fn keys() -> Data<Auth> {
+        let dir = std::env::temp_dir().join(format!("actix-rhai-auth-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let keys_file = dir.join("keys.json");
+        let keys = json!({ "keys": [
+            { "name": "ci", "key": "ci-key", "scripts": ["*"] },
+            { "name": "ops", "key": "ops-key", "admin": true },
+        ] });
+        std::fs::write(&keys_file, keys.to_string()).unwrap();
+        let audit_log = dir.join("audit.log");
+        Data::new(Auth::load(&keys_file, Some(&audit_log)).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_are_checked_on_the_decoded_path() {
+        let app = test::init_service(
+            App::new()
+                .app_data(keys())
+                .wrap(from_fn(check))
+                .route("/admin/state", web::get().to(HttpResponse::Ok))
+                .route("/repl", web::get().to(HttpResponse::Ok))
+                .route("/scripts", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let status = |path: &'static str, key: &'static str| {
+            let req = test::TestRequest::get().uri(path).insert_header(("X-API-Key", key)).to_request();
+            let app = &app;
+            async move { test::call_service(app, req).await.status().as_u16() }
+        };
+
+        for path in ["/admin/state", "/%61dmin/state", "/%61%64%6d%69%6e/state", "/repl", "/%72epl"] {
+            assert_eq!(status(path, "ci-key").await, 403, "{path}");
+            assert_eq!(status(path, "ops-key").await, 200, "{path}");
+        }
+        assert_eq!(status("/scripts", "ci-key").await, 200);
+        assert_eq!(status("/%73cripts", "ci-key").await, 200);
+    }
+}
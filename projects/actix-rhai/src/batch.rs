@@ -0,0 +1,236 @@
+use crate::auth::Caller;
+use crate::error::{ErrorKind, ScriptError};
+use crate::params::Args;
+use crate::scripts::{result_type, ScriptHost};
+use actix_web::{
+    post,
+    web::{self, Bytes, Data, Query},
+    HttpRequest, HttpResponse,
+};
+use futures_util::future::{join_all, BoxFuture};
+use rhai::{Array, Dynamic, Map};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::Span;
+
+/// Most operations accepted in one batch, so a single request can't take over the
+/// whole blocking thread pool.
+const MAX_OPERATIONS: usize = 100;
+
+/// One entry of a batch: the script to run and its arguments, either positional
+/// (`[1, 2]`) or named (`{"num1": 1, "num2": 2}`).
+#[derive(Deserialize)]
+struct Operation {
+    script: String,
+    #[serde(default)]
+    args: Value,
+}
+
+/// What to do with the rest of a batch once an operation fails.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    /// Run everything and report each failure in place.
+    #[default]
+    Continue,
+    /// Don't start anything else; operations not yet started report `cancelled`. Those
+    /// already running are not interrupted and report their own outcome.
+    FailFast,
+}
+
+#[derive(Deserialize)]
+struct BatchQuery {
+    #[serde(default)]
+    mode: Mode,
+}
+
+/// Result of one operation, in the same position as the operation in the request.
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub enum Outcome {
+    Ok {
+        #[serde(serialize_with = "exact")]
+        result: Dynamic,
+        #[serde(rename = "type")]
+        ty: &'static str,
+    },
+    Err {
+        error: ScriptError,
+    },
+}
+
+impl From<Result<Dynamic, ScriptError>> for Outcome {
+    fn from(result: Result<Dynamic, ScriptError>) -> Self {
+        match result {
+            Ok(result) => Outcome::Ok {
+                ty: result_type(&result),
+                result,
+            },
+            Err(error) => Outcome::Err { error },
+        }
+    }
+}
+
+/// Serializes a result with any decimals in it as strings: they hold more digits than a
+/// JSON number read as `f64` keeps.
+fn exact<S: Serializer>(value: &Dynamic, serializer: S) -> Result<S::Ok, S::Error> {
+    Exact(value).serialize(serializer)
+}
+
+struct Exact<'a>(&'a Dynamic);
+
+impl Serialize for Exact<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Ok(decimal) = self.0.as_decimal() {
+            serializer.collect_str(&decimal)
+        } else if let Some(array) = self.0.read_lock::<Array>() {
+            serializer.collect_seq(array.iter().map(Exact))
+        } else if let Some(map) = self.0.read_lock::<Map>() {
+            serializer.collect_map(map.iter().map(|(key, value)| (key.as_str(), Exact(value))))
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+/// Runs a JSON array of `{script, args}` operations and returns an array of results.
+///
+/// Operations run in parallel, except that calls to a script declared
+/// `//! sequential: true` run one at a time in the order they were given.
+/// `?mode=fail_fast` stops starting new operations after the first failure, but lets
+/// those already running finish. Each operation is checked against the caller's
+/// permissions separately.
+#[post("/batch")]
+pub async fn batch(
+    req: HttpRequest,
+    host: Data<ScriptHost>,
+    query: Query<BatchQuery>,
+    body: Bytes,
+) -> Result<HttpResponse, ScriptError> {
+    let operations: Vec<Operation> = serde_json::from_slice(&body).map_err(|err| {
+        ScriptError::new(
+            ErrorKind::InvalidArgument,
+            format!("request body must be a JSON array of {{script, args}} objects: {err}"),
+        )
+    })?;
+    if operations.len() > MAX_OPERATIONS {
+        return Err(ScriptError::new(
+            ErrorKind::InvalidArgument,
+            format!("a batch may hold at most {MAX_OPERATIONS} operations, found {}", operations.len()),
+        ));
+    }
+
+    // Looking a script up may compile it, which has no place on an async worker.
+    let names: BTreeSet<String> = operations.iter().map(|op| op.script.clone()).collect();
+    let lookup = host.clone();
+    let sequential_scripts: BTreeSet<String> = web::block(move || {
+        names
+            .into_iter()
+            .filter(|name| matches!(lookup.script(name), Some(Ok(script)) if script.meta.sequential))
+            .collect()
+    })
+    .await
+    .map_err(blocking_error)?;
+
+    let mode = query.mode;
+    let caller = Caller::of(&req);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let mut outcomes: Vec<Option<Outcome>> = (0..operations.len()).map(|_| None).collect();
+
+    // Parallel operations get a task each; sequential ones get one task per script.
+    let mut parallel = Vec::new();
+    let mut sequential: BTreeMap<String, Vec<(usize, Args)>> = BTreeMap::new();
+    for (i, op) in operations.into_iter().enumerate() {
+        let args = match Args::from_json(op.args) {
+            Ok(args) => args,
+            Err(error) => {
+                if mode == Mode::FailFast {
+                    cancelled.store(true, Ordering::Release);
+                }
+                outcomes[i] = Some(Outcome::Err { error });
+                continue;
+            }
+        };
+        if sequential_scripts.contains(&op.script) {
+            sequential.entry(op.script).or_default().push((i, args));
+        } else {
+            parallel.push((i, op.script, args));
+        }
+    }
+
+    let mut tasks: Vec<BoxFuture<'static, Vec<(usize, Outcome)>>> = Vec::new();
+    for (i, script, args) in parallel {
+        let (host, cancelled, caller) = (host.clone(), cancelled.clone(), caller.clone());
+        let span = Span::current();
+        tasks.push(Box::pin(async move {
+            let outcome = web::block(move || {
+                let _entered = span.enter();
+                call(&host, caller.as_ref(), &cancelled, mode, &script, args)
+            })
+            .await;
+            vec![(i, outcome.unwrap_or_else(|err| Outcome::Err { error: blocking_error(err) }))]
+        }));
+    }
+    for (script, calls) in sequential {
+        let (host, cancelled, caller) = (host.clone(), cancelled.clone(), caller.clone());
+        let indices: Vec<usize> = calls.iter().map(|(i, _)| *i).collect();
+        let span = Span::current();
+        tasks.push(Box::pin(async move {
+            let outcomes = web::block(move || {
+                let _entered = span.enter();
+                calls
+                    .into_iter()
+                    .map(|(i, args)| (i, call(&host, caller.as_ref(), &cancelled, mode, &script, args)))
+                    .collect()
+            })
+            .await;
+            outcomes.unwrap_or_else(|err| {
+                let error = blocking_error(err);
+                indices.into_iter().map(|i| (i, Outcome::Err { error: error.clone() })).collect()
+            })
+        }));
+    }
+
+    for (i, outcome) in join_all(tasks).await.into_iter().flatten() {
+        outcomes[i] = Some(outcome);
+    }
+    let outcomes: Vec<Outcome> = outcomes
+        .into_iter()
+        .map(|outcome| outcome.expect("every operation has an outcome"))
+        .collect();
+    Ok(HttpResponse::Ok().json(outcomes))
+}
+
+/// Runs one operation unless the batch has already been cancelled.
+fn call(
+    host: &ScriptHost,
+    caller: Option<&Caller>,
+    cancelled: &AtomicBool,
+    mode: Mode,
+    script: &str,
+    args: Args,
+) -> Outcome {
+    if cancelled.load(Ordering::Acquire) {
+        let error = ScriptError::new(ErrorKind::Cancelled, "not run: an earlier operation in the batch failed");
+        return Outcome::Err { error };
+    }
+    let result = match caller {
+        Some(caller) => caller.authorize(script).and_then(|_| {
+            let result = host.call(script, args);
+            caller.audit(script, result.as_ref().err());
+            result
+        }),
+        None => host.call(script, args),
+    };
+    if result.is_err() && mode == Mode::FailFast {
+        cancelled.store(true, Ordering::Release);
+    }
+    result.into()
+}
+
+fn blocking_error(err: actix_web::error::BlockingError) -> ScriptError {
+    ScriptError::new(ErrorKind::Runtime, err.to_string())
+}
@@ -0,0 +1,178 @@
+//! Runs scripts against the expectations in a sidecar file next to each of them,
+//! `add.rhai` -> `add.test.json`:
+//!
+//! ```json
+//! [
+//!     { "name": "small numbers", "args": [2, 3], "expect": 5 },
+//!     { "args": { "num1": 2, "num2": 3 }, "expect": 5, "type": "int" },
+//!     { "args": [9223372036854775807, 1], "error": "overflow" }
+//! ]
+//! ```
+//!
+//! `args` is passed exactly like a `/batch` operation's (positional or named) and bound
+//! by the same code as an HTTP request. Scripts run on engines built from the same
+//! config as the server (limits, overflow policy, disabled symbols, lib directory), with
+//! a throwaway state store per script. A case checks any of `expect` (the result as
+//! JSON), `type` (as in `X-Result-Type`) and `error` (the error `kind`).
+//!
+//! With no script arguments, every script in `scripts_dir` that has a sidecar is run.
+//! Exits with 1 if any case fails.
+
+use actix_rhai::config::Config;
+use actix_rhai::error::ScriptError;
+use actix_rhai::params::Args;
+use actix_rhai::scripts::{result_type, script_name, ScriptHost};
+use actix_rhai::state::StateStore;
+use rhai::Dynamic;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: Option<String>,
+    #[serde(default)]
+    args: Value,
+    expect: Option<Value>,
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct Summary {
+    passed: usize,
+    failed: usize,
+}
+
+fn main() -> ExitCode {
+    let (config, operands) = match Config::load_with_operands("[SCRIPT.rhai]...") {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let scripts = if operands.is_empty() {
+        match with_sidecars(&config.scripts_dir) {
+            Ok(scripts) => scripts,
+            Err(err) => {
+                eprintln!("cannot list {}: {err}", config.scripts_dir.display());
+                return ExitCode::from(2);
+            }
+        }
+    } else {
+        operands.into_iter().map(PathBuf::from).collect()
+    };
+
+    let mut summary = Summary::default();
+    for script in &scripts {
+        println!("{}", script.display());
+        if let Err(problem) = run_file(&config, script, &mut summary) {
+            println!("  FAIL  {problem}");
+            summary.failed += 1;
+        }
+    }
+
+    println!("\n{} passed, {} failed", summary.passed, summary.failed);
+    if summary.failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Every script in `dir` that has a sidecar file, in name order.
+fn with_sidecars(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut scripts = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if script_name(&path).is_some() && sidecar(&path).is_file() {
+            scripts.push(path);
+        }
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+fn sidecar(script: &Path) -> PathBuf {
+    script.with_extension("test.json")
+}
+
+/// Runs every case for `script`; `Err` if the script or its sidecar can't be used at all.
+fn run_file(config: &Config, script: &Path, summary: &mut Summary) -> Result<(), String> {
+    let name = script_name(script).ok_or("not a .rhai file")?;
+    let sidecar = sidecar(script);
+    let text = std::fs::read_to_string(&sidecar).map_err(|err| format!("cannot read {}: {err}", sidecar.display()))?;
+    let cases: Vec<Case> = serde_json::from_str(&text).map_err(|err| format!("{}: {err}", sidecar.display()))?;
+
+    let state = StateStore::in_memory().map_err(|err| err.to_string())?;
+    let host = ScriptHost::new(
+        config.limits.limits(),
+        config.overflow,
+        config.disabled_symbols.clone(),
+        Arc::new(state),
+        &config.lib_dir,
+    );
+    host.add(name.clone(), script).map_err(|err| format!("does not compile: {err}"))?;
+
+    for (i, case) in cases.into_iter().enumerate() {
+        let label = case.name.clone().unwrap_or_else(|| format!("case {}", i + 1));
+        let result = Args::from_json(case.args.clone()).and_then(|args| host.call(&name, args));
+        match check(&case, &result) {
+            Ok(()) => {
+                println!("  ok    {label}");
+                summary.passed += 1;
+            }
+            Err(problem) => {
+                println!("  FAIL  {label}: {problem}");
+                summary.failed += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check(case: &Case, result: &Result<Dynamic, ScriptError>) -> Result<(), String> {
+    if case.expect.is_none() && case.ty.is_none() && case.error.is_none() {
+        return Err("nothing to check; give `expect`, `type` or `error`".into());
+    }
+    match (result, &case.error) {
+        (Err(err), Some(kind)) if err.kind.to_string() == *kind => Ok(()),
+        (Err(err), _) => Err(format!("failed with {}: {err}", err.kind)),
+        (Ok(value), Some(kind)) => Err(format!("expected a `{kind}` error, got {value}")),
+        (Ok(value), None) => {
+            let actual = serde_json::to_value(value).map_err(|err| err.to_string())?;
+            if let Some(expected) = &case.expect
+                && !same(expected, &actual)
+            {
+                return Err(format!("expected {expected}, got {actual}"));
+            }
+            if let Some(ty) = &case.ty
+                && result_type(value) != ty
+            {
+                return Err(format!("expected type `{ty}`, got `{}`", result_type(value)));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// JSON equality, except that numbers compare by value (`5` matches `5.0`). Integers
+/// compare exactly; only when either side is a float do both go through `f64`, which
+/// would otherwise make integers past 2^53 equal to their neighbours.
+fn same(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Number(a), Value::Number(b)) if a.is_f64() || b.is_f64() => a.as_f64() == b.as_f64(),
+        (Value::Number(a), Value::Number(b)) => a.as_i64() == b.as_i64() && a.as_u64() == b.as_u64(),
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b)),
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| same(a, b)))
+        }
+        _ => expected == actual,
+    }
+}
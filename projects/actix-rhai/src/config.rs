@@ -0,0 +1,505 @@
+use crate::arith::Overflow;
+use crate::jobs;
+use crate::params::Args;
+use crate::sandbox::{Limits, DEFAULT_DISABLED_SYMBOLS};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Config file read from the [base directory](base_dir) when neither `--config` nor
+/// `RHAI_CONFIG` names one.
+const DEFAULT_CONFIG_FILE: &str = "actix-rhai.json";
+
+/// Settings that are on when given as a bare flag, e.g. `--debug`; any other flag takes
+/// the next argument as its value.
+const SWITCHES: &[&str] = &["debug", "repl_enabled"];
+
+/// Every setting that can be overridden, with the environment variable that overrides
+/// it. The command-line flag is the setting's name with dashes, e.g. `--scripts-dir`.
+const SETTINGS: &[(&str, &str)] = &[
+    ("bind", "RHAI_BIND"),
+    ("port", "RHAI_PORT"),
+    ("workers", "RHAI_WORKERS"),
+    ("shutdown_timeout_secs", "RHAI_SHUTDOWN_TIMEOUT_SECS"),
+    ("scripts_dir", "RHAI_SCRIPTS_DIR"),
+    ("lib_dir", "RHAI_LIB_DIR"),
+    ("state_db", "RHAI_STATE_DB"),
+    ("overflow", "RHAI_OVERFLOW"),
+    ("debug", "RHAI_DEBUG"),
+    ("disabled_symbols", "RHAI_DISABLED_SYMBOLS"), // comma-separated; empty disables nothing
+    ("max_operations", "RHAI_MAX_OPERATIONS"),
+    ("max_call_levels", "RHAI_MAX_CALL_LEVELS"),
+    ("max_string_size", "RHAI_MAX_STRING_SIZE"),
+    ("max_array_size", "RHAI_MAX_ARRAY_SIZE"),
+    ("max_map_size", "RHAI_MAX_MAP_SIZE"),
+    ("timeout_ms", "RHAI_TIMEOUT_MS"),
+    ("repl_enabled", "RHAI_REPL_ENABLED"),
+    ("repl_max_sessions", "RHAI_REPL_MAX_SESSIONS"),
+    ("repl_idle_timeout_secs", "RHAI_REPL_IDLE_TIMEOUT_SECS"),
+    ("auth_keys_file", "RHAI_AUTH_KEYS_FILE"),
+    ("auth_audit_log", "RHAI_AUTH_AUDIT_LOG"),
+    ("memo_capacity", "RHAI_MEMO_CAPACITY"),
+    ("memo_ttl_secs", "RHAI_MEMO_TTL_SECS"),
+    ("scheduler_history", "RHAI_SCHEDULER_HISTORY"),
+];
+
+/// Server settings, read from a JSON config file and then overridden by environment
+/// variables and finally by command-line flags:
+///
+/// ```json
+/// {
+///     "bind": "127.0.0.1",
+///     "port": 8080,
+///     "workers": 4,
+///     "shutdown_timeout_secs": 30,
+///     "scripts_dir": "src",
+///     "lib_dir": "lib",
+///     "state_db": "state.redb",
+///     "overflow": "reject",
+///     "debug": false,
+///     "disabled_symbols": ["eval"],
+///     "limits": { "max_operations": 1000000, "timeout_ms": 1000 },
+///     "repl": { "enabled": true, "max_sessions": 4, "idle_timeout_secs": 300 },
+///     "auth": { "keys_file": "keys.json", "audit_log": "audit.log" },
+///     "memo": { "capacity": 1024, "ttl_secs": 300 },
+///     "scheduler": {
+///         "history": 20,
+///         "jobs": [{ "name": "cleanup", "schedule": "*/5 * * * *", "script": "cleanup", "args": {} }]
+///     }
+/// }
+/// ```
+///
+/// Every key is optional. Relative paths in the file are relative to the file itself;
+/// relative paths given through the environment or flags to the working directory. The
+/// defaults (`src`, `lib`, `state.redb` and the config file `actix-rhai.json`) are in the
+/// [base directory](base_dir), wherever the server is started from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub workers: Option<usize>, // `None` starts one worker per CPU core
+    /// How long requests still running at shutdown get to finish before they are dropped.
+    pub shutdown_timeout_secs: u64,
+    pub scripts_dir: PathBuf,
+    pub lib_dir: PathBuf,
+    pub state_db: PathBuf,
+    pub overflow: Overflow,
+    /// Log at `debug` level, including what scripts `print`.
+    pub debug: bool,
+    pub disabled_symbols: Vec<String>,
+    pub limits: LimitsConfig,
+    pub repl: ReplConfig,
+    pub auth: AuthConfig,
+    pub memo: MemoConfig,
+    pub scheduler: SchedulerConfig,
+}
+
+/// Server-wide script limits; see [`Limits`]. Scripts can still override them in
+/// their header.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_operations: u64,
+    pub max_call_levels: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+    pub timeout_ms: u64,
+}
+
+/// The WebSocket REPL at `/repl`. It can run anything against the live server, so it
+/// is off unless turned on here.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplConfig {
+    pub enabled: bool,
+    pub max_sessions: usize,
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for ReplConfig {
+    fn default() -> Self {
+        ReplConfig {
+            enabled: false,
+            max_sessions: 4,
+            idle_timeout_secs: 300,
+        }
+    }
+}
+
+/// Credentials required to use the server; see `auth`. Without a keys file anyone who
+/// can reach the port may call anything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub keys_file: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>, // `None` writes the audit trail to stdout
+}
+
+/// The cache of results of scripts declared `pure`; a capacity of 0 turns it off.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoConfig {
+    pub capacity: usize,
+    pub ttl_secs: u64,
+}
+
+impl Default for MemoConfig {
+    fn default() -> Self {
+        MemoConfig {
+            capacity: 1024,
+            ttl_secs: 300,
+        }
+    }
+}
+
+/// Scripts run on a schedule; see `jobs`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    pub history: usize, // runs kept per job for `/admin/jobs`
+    pub jobs: Vec<JobConfig>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            history: 20,
+            jobs: Vec::new(),
+        }
+    }
+}
+
+/// One scheduled job: run `script` with `args` (as in a `/batch` operation) whenever the
+/// cron expression `schedule` comes round.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobConfig {
+    pub name: String,
+    pub schedule: String,
+    pub script: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1".into(),
+            port: 8080,
+            workers: None,
+            shutdown_timeout_secs: 30,
+            scripts_dir: base_dir().join("src"),
+            lib_dir: base_dir().join("lib"),
+            state_db: base_dir().join("state.redb"),
+            overflow: Overflow::default(),
+            debug: false,
+            disabled_symbols: DEFAULT_DISABLED_SYMBOLS.iter().map(|s| s.to_string()).collect(),
+            limits: LimitsConfig::default(),
+            repl: ReplConfig::default(),
+            auth: AuthConfig::default(),
+            memo: MemoConfig::default(),
+            scheduler: SchedulerConfig::default(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        LimitsConfig {
+            max_operations: limits.max_operations,
+            max_call_levels: limits.max_call_levels,
+            max_string_size: limits.max_string_size,
+            max_array_size: limits.max_array_size,
+            max_map_size: limits.max_map_size,
+            timeout_ms: limits.timeout.as_millis() as u64,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_operations: self.max_operations,
+            max_call_levels: self.max_call_levels,
+            max_string_size: self.max_string_size,
+            max_array_size: self.max_array_size,
+            max_map_size: self.max_map_size,
+            timeout: Duration::from_millis(self.timeout_ms),
+        }
+    }
+}
+
+/// Everything wrong with the configuration, reported together so it can be fixed in
+/// one go.
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Builds the configuration from the config file, the environment and the
+    /// command-line arguments, and checks it.
+    ///
+    /// `--help` prints the available flags and exits.
+    pub fn load() -> Result<Config, ConfigError> {
+        let (config, operands) = Config::load_with_operands("")?;
+        match operands.first() {
+            Some(arg) => Err(ConfigError(vec![format!("unexpected argument `{arg}`")])),
+            None => Ok(config),
+        }
+    }
+
+    /// Like [`load`](Self::load), but for programs that take arguments of their own:
+    /// whatever on the command line is not a flag is returned instead of rejected.
+    /// `usage` describes those arguments for `--help`.
+    pub fn load_with_operands(usage: &str) -> Result<(Config, Vec<String>), ConfigError> {
+        let mut problems = Vec::new();
+        let (config_file, flags, operands) = parse_args(std::env::args(), usage, &mut problems);
+
+        let config_file = config_file
+            .or_else(|| std::env::var_os("RHAI_CONFIG").map(PathBuf::from))
+            .or_else(|| Some(base_dir().join(DEFAULT_CONFIG_FILE)).filter(|path| path.is_file()));
+        let mut config = match config_file {
+            Some(path) => Config::from_file(&path).unwrap_or_else(|problem| {
+                problems.push(problem);
+                Config::default()
+            }),
+            None => Config::default(),
+        };
+
+        for (setting, var) in SETTINGS {
+            if let Ok(value) = std::env::var(var)
+                && let Err(problem) = config.set(setting, &value)
+            {
+                problems.push(format!("{var}: {problem}"));
+            }
+        }
+        for (setting, value) in flags {
+            if let Err(problem) = config.set(&setting, &value) {
+                problems.push(format!("--{}: {problem}", setting.replace('_', "-")));
+            }
+        }
+
+        problems.extend(config.check());
+        if problems.is_empty() {
+            Ok((config, operands))
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read {}: {err}", path.display()))?;
+        let mut config: Config = serde_json::from_str(&text).map_err(|err| format!("{}: {err}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for path in [&mut config.scripts_dir, &mut config.lib_dir, &mut config.state_db] {
+            *path = dir.join(&*path);
+        }
+        for path in [&mut config.auth.keys_file, &mut config.auth.audit_log].into_iter().flatten() {
+            *path = dir.join(&*path);
+        }
+        Ok(config)
+    }
+
+    /// Overrides one setting from its text form.
+    fn set(&mut self, setting: &str, value: &str) -> Result<(), String> {
+        let limits = &mut self.limits;
+        match setting {
+            "bind" => self.bind = value.to_string(),
+            "port" => self.port = parse(value)?,
+            "workers" => self.workers = Some(parse(value)?),
+            "shutdown_timeout_secs" => self.shutdown_timeout_secs = parse(value)?,
+            "scripts_dir" => self.scripts_dir = value.into(),
+            "lib_dir" => self.lib_dir = value.into(),
+            "state_db" => self.state_db = value.into(),
+            "overflow" => {
+                self.overflow = Overflow::parse(value)
+                    .ok_or_else(|| format!("unknown policy `{value}` (expected reject, saturate or promote)"))?
+            }
+            "debug" => self.debug = parse(value)?,
+            "disabled_symbols" => {
+                self.disabled_symbols =
+                    value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect()
+            }
+            "max_operations" => limits.max_operations = parse(value)?,
+            "max_call_levels" => limits.max_call_levels = parse(value)?,
+            "max_string_size" => limits.max_string_size = parse(value)?,
+            "max_array_size" => limits.max_array_size = parse(value)?,
+            "max_map_size" => limits.max_map_size = parse(value)?,
+            "timeout_ms" => limits.timeout_ms = parse(value)?,
+            "repl_enabled" => self.repl.enabled = parse(value)?,
+            "repl_max_sessions" => self.repl.max_sessions = parse(value)?,
+            "repl_idle_timeout_secs" => self.repl.idle_timeout_secs = parse(value)?,
+            "auth_keys_file" => self.auth.keys_file = Some(value.into()).filter(|_| !value.is_empty()),
+            "auth_audit_log" => self.auth.audit_log = Some(value.into()).filter(|_| !value.is_empty()),
+            "memo_capacity" => self.memo.capacity = parse(value)?,
+            "memo_ttl_secs" => self.memo.ttl_secs = parse(value)?,
+            "scheduler_history" => self.scheduler.history = parse(value)?,
+            _ => return Err("unknown setting".into()),
+        }
+        Ok(())
+    }
+
+    /// Problems that would only surface once the server is running.
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(err) = (self.bind.as_str(), self.port).to_socket_addrs() {
+            problems.push(format!("bind: cannot use `{}:{}`: {err}", self.bind, self.port));
+        }
+        if self.workers == Some(0) {
+            problems.push("workers: must be at least 1".into());
+        }
+        for (setting, dir) in [("scripts_dir", &self.scripts_dir), ("lib_dir", &self.lib_dir)] {
+            if !dir.is_dir() {
+                problems.push(format!("{setting}: {} is not a directory", dir.display()));
+            }
+        }
+        let state_dir = self.state_db.parent().filter(|dir| !dir.as_os_str().is_empty());
+        if state_dir.is_some_and(|dir| !dir.is_dir()) {
+            problems.push(format!("state_db: the directory of {} does not exist", self.state_db.display()));
+        }
+        let limits = &self.limits;
+        for (setting, value) in [
+            ("max_operations", limits.max_operations),
+            ("max_call_levels", limits.max_call_levels as u64),
+            ("max_string_size", limits.max_string_size as u64),
+            ("max_array_size", limits.max_array_size as u64),
+            ("max_map_size", limits.max_map_size as u64),
+            ("timeout_ms", limits.timeout_ms),
+        ] {
+            // Rhai reads zero as "unlimited", which is never what a config means to say.
+            if value == 0 {
+                problems.push(format!("limits.{setting}: must be greater than 0"));
+            }
+        }
+        if let Some(keys_file) = &self.auth.keys_file
+            && !keys_file.is_file()
+        {
+            problems.push(format!("auth.keys_file: {} is not a file", keys_file.display()));
+        }
+        let audit_dir = self.auth.audit_log.as_deref().and_then(Path::parent).filter(|dir| !dir.as_os_str().is_empty());
+        if audit_dir.is_some_and(|dir| !dir.is_dir()) {
+            problems.push("auth.audit_log: its directory does not exist".into());
+        }
+        if self.repl.enabled && self.repl.max_sessions == 0 {
+            problems.push("repl.max_sessions: must be at least 1 when the REPL is enabled".into());
+        }
+        if self.repl.enabled && self.repl.idle_timeout_secs == 0 {
+            problems.push("repl.idle_timeout_secs: must be greater than 0".into());
+        }
+        if self.memo.capacity > 0 && self.memo.ttl_secs == 0 {
+            problems.push("memo.ttl_secs: must be greater than 0 (set memo.capacity to 0 to turn memoization off)".into());
+        }
+        if self.scheduler.history == 0 {
+            problems.push("scheduler.history: must be at least 1".into());
+        }
+        let jobs = &self.scheduler.jobs;
+        for (i, job) in jobs.iter().enumerate() {
+            let problem = |problem: String| format!("scheduler.jobs[{i}] (`{}`): {problem}", job.name);
+            if job.name.is_empty() {
+                problems.push(problem("name: must not be empty".into()));
+            } else if jobs[..i].iter().any(|other| other.name == job.name) {
+                problems.push(problem("name: used by an earlier job too".into()));
+            }
+            if let Err(err) = jobs::parse_schedule(&job.schedule) {
+                problems.push(problem(format!("schedule: {err}")));
+            }
+            if let Err(err) = Args::from_json(job.args.clone()) {
+                problems.push(problem(format!("args: {}", err.message)));
+            }
+        }
+        problems
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|err| format!("invalid value `{value}`: {err}"))
+}
+
+/// Splits the command line (program name first) into the `--config` file, if any,
+/// `(setting, value)` overrides and the remaining operands. Flags take their value
+/// either as `--port 9000` or `--port=9000`.
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+    usage: &str,
+    problems: &mut Vec<String>,
+) -> (Option<PathBuf>, Vec<(String, String)>, Vec<String>) {
+    let program = args.next().unwrap_or_default();
+    let mut config_file = None;
+    let mut flags = Vec::new();
+    let mut operands = Vec::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            print_usage(&program, usage);
+            std::process::exit(0);
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            operands.push(arg);
+            continue;
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None if SWITCHES.contains(&flag.replace('-', "_").as_str()) => (flag.to_string(), Some("true".to_string())),
+            None => (flag.to_string(), args.next_if(|next| !next.starts_with("--"))),
+        };
+        let Some(value) = value else {
+            problems.push(format!("--{name}: missing value"));
+            continue;
+        };
+        let setting = name.replace('-', "_");
+        if setting == "config" {
+            config_file = Some(PathBuf::from(value));
+        } else if SETTINGS.iter().any(|(known, _)| *known == setting) {
+            flags.push((setting, value));
+        } else {
+            problems.push(format!("unknown flag `--{name}`"));
+        }
+    }
+    (config_file, flags, operands)
+}
+
+/// Directory the default paths are in: the crate root when started through `cargo run`,
+/// which sets `CARGO_MANIFEST_DIR` for the program it runs, and otherwise the directory
+/// holding the executable, so an installed server keeps `actix-rhai.json`, `src/` and
+/// `lib/` next to the binary. Either way it doesn't depend on the working directory.
+pub fn base_dir() -> PathBuf {
+    std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .or_else(|| Some(std::env::current_exe().ok()?.parent()?.to_path_buf()))
+        .unwrap_or_default()
+}
+
+fn print_usage(program: &str, usage: &str) {
+    let program = Path::new(program).file_name().unwrap_or_default().to_string_lossy();
+    println!("usage: {program} [--config FILE] [--SETTING VALUE]... {usage}\n");
+    let base = base_dir();
+    println!("Settings are read from FILE (default {}, or RHAI_CONFIG),", base.join(DEFAULT_CONFIG_FILE).display());
+    println!("then from the environment, then from the flags below. Relative paths are relative");
+    println!("to FILE, or else to the working directory. Paths not set are src, lib and");
+    println!("state.redb in {}.", base.display());
+    println!("Switches (--debug, --repl-enabled) need no value; --debug=false turns one off.\n");
+    for (setting, var) in SETTINGS {
+        println!("  --{:<20} {var}", setting.replace('_', "-"));
+    }
+}
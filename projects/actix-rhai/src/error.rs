@@ -0,0 +1,210 @@
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
+use rhai::{EvalAltResult, ParseError, Position};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What went wrong while running a script; serialized as the `kind` field of the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NotFound,
+    InvalidArgument,
+    Parse,
+    Import,
+    Runtime,
+    TypeMismatch,
+    LimitExceeded,
+    Timeout,
+    Overflow,
+    Storage,
+    Cancelled,
+    Unauthorized,
+    Forbidden,
+    RateLimited,
+}
+
+impl ErrorKind {
+    /// Every kind, for documenting the error schema.
+    pub const ALL: &[ErrorKind] = &[
+        ErrorKind::NotFound,
+        ErrorKind::InvalidArgument,
+        ErrorKind::Parse,
+        ErrorKind::Import,
+        ErrorKind::Runtime,
+        ErrorKind::TypeMismatch,
+        ErrorKind::LimitExceeded,
+        ErrorKind::Timeout,
+        ErrorKind::Overflow,
+        ErrorKind::Storage,
+        ErrorKind::Cancelled,
+        ErrorKind::Unauthorized,
+        ErrorKind::Forbidden,
+        ErrorKind::RateLimited,
+    ];
+}
+
+impl fmt::Display for ErrorKind {
+    /// The same snake_case name as in the JSON body.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+        f.write_str(name.as_str().unwrap_or_default())
+    }
+}
+
+/// Error returned by the script routes, rendered as a JSON body instead of a panic:
+///
+/// ```json
+/// { "kind": "parse", "message": "Syntax error: ...", "line": 1, "column": 4 }
+/// ```
+///
+/// Rejected arguments additionally carry a `fields` object mapping each offending
+/// parameter to what was wrong with it, and rate-limited requests a `retry_after` in
+/// seconds (also sent as the `Retry-After` header).
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+impl ScriptError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        ScriptError {
+            kind,
+            message: message.into(),
+            line: None,
+            column: None,
+            fields: BTreeMap::new(),
+            retry_after: None,
+        }
+    }
+
+    pub fn invalid_arguments(fields: BTreeMap<String, String>) -> Self {
+        ScriptError {
+            fields,
+            ..Self::new(ErrorKind::InvalidArgument, "invalid arguments")
+        }
+    }
+
+    pub fn not_found(script: &str) -> Self {
+        Self::new(ErrorKind::NotFound, format!("unknown script `{script}`"))
+    }
+
+    fn at(mut self, pos: Position) -> Self {
+        self.line = pos.line();
+        self.column = pos.position();
+        self
+    }
+}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(err: Box<EvalAltResult>) -> Self {
+        // Errors raised inside a script function come wrapped in the call that failed;
+        // the innermost one carries the real cause, the deepest known position the line
+        // it happened on (native functions such as `/` report none of their own).
+        // Errors in an imported library file additionally name the chain of modules.
+        let mut err = &*err;
+        let mut pos = err.position();
+        let mut modules = Vec::new();
+        while let EvalAltResult::ErrorInFunctionCall(.., inner, _)
+        | EvalAltResult::ErrorInModule(.., inner, _) = err
+        {
+            if let EvalAltResult::ErrorInModule(module, ..) = err {
+                modules.push(module.as_str());
+            }
+            err = inner;
+            if !err.position().is_none() {
+                pos = err.position();
+            }
+        }
+        let kind = match err {
+            EvalAltResult::ErrorParsing(..) => ErrorKind::Parse,
+            EvalAltResult::ErrorMismatchOutputType(..) => ErrorKind::TypeMismatch,
+            EvalAltResult::ErrorSystem(..) => ErrorKind::NotFound, // the script file could not be read
+            EvalAltResult::ErrorTerminated(..) => ErrorKind::Timeout,
+            EvalAltResult::ErrorModuleNotFound(..) => ErrorKind::Import,
+            // A library file that fails while being evaluated, e.g. a circular import.
+            EvalAltResult::ErrorRuntime(..) if !modules.is_empty() => ErrorKind::Import,
+            EvalAltResult::ErrorArithmetic(message, ..) if message.contains("overflow") => ErrorKind::Overflow,
+            EvalAltResult::ErrorTooManyOperations(..)
+            | EvalAltResult::ErrorTooManyModules(..)
+            | EvalAltResult::ErrorStackOverflow(..)
+            | EvalAltResult::ErrorDataTooLarge(..) => ErrorKind::LimitExceeded,
+            _ => ErrorKind::Runtime,
+        };
+        let message = match err {
+            // Raised by the library resolver itself, e.g. for a circular import.
+            EvalAltResult::ErrorRuntime(message, ..) if !modules.is_empty() && message.is_string() => message.to_string(),
+            _ => err.to_string(),
+        };
+        let message = match message.strip_suffix(&format!(" ({})", err.position())) {
+            Some(message) if !err.position().is_none() => message.to_string(),
+            _ => message,
+        };
+        let message = match modules.as_slice() {
+            [] => message,
+            modules => format!("{message} in module {}", modules.join(" -> ")),
+        };
+        ScriptError::new(kind, message).at(pos)
+    }
+}
+
+impl From<ParseError> for ScriptError {
+    fn from(err: ParseError) -> Self {
+        Box::<EvalAltResult>::from(err).into()
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " (line {line}, column {column})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl ResponseError for ScriptError {
+    fn status_code(&self) -> StatusCode {
+        match self.kind {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::InvalidArgument => StatusCode::BAD_REQUEST,
+            ErrorKind::LimitExceeded | ErrorKind::Overflow => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::Cancelled => StatusCode::FAILED_DEPENDENCY,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ErrorKind::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::Parse
+            | ErrorKind::Import
+            | ErrorKind::Runtime
+            | ErrorKind::TypeMismatch
+            | ErrorKind::Storage => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.kind == ErrorKind::Unauthorized {
+            response.insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"actix-rhai\""));
+        }
+        if let Some(seconds) = self.retry_after {
+            response.insert_header((header::RETRY_AFTER, seconds));
+        }
+        response.json(self)
+    }
+}
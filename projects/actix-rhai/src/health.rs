@@ -0,0 +1,24 @@
+use crate::scripts::ScriptHost;
+use actix_web::{get, web::Data, HttpResponse, Responder};
+use serde_json::json;
+
+/// Liveness: answers as long as the server is up.
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Readiness: fails with 503 while any script has never compiled or while a change on
+/// disk is being reloaded, and says which.
+#[get("/readyz")]
+pub async fn readyz(host: Data<ScriptHost>) -> impl Responder {
+    let uncompiled = host.uncompiled();
+    let reloading = host.reloading();
+    let ready = uncompiled.is_empty() && !reloading;
+    let body = json!({ "ready": ready, "uncompiled": uncompiled, "reloading": reloading });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
@@ -0,0 +1,161 @@
+use crate::batch::Outcome;
+use crate::config::{JobConfig, SchedulerConfig};
+use crate::error::{ErrorKind, ScriptError};
+use crate::params::Args;
+use crate::scripts::ScriptHost;
+use actix_web::{
+    get,
+    rt::{self, time::sleep},
+    web::{self, Data, Json},
+    Responder,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use cron::Schedule;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{info, info_span, warn, Instrument};
+
+/// Parses a cron expression: either the classic five fields (minute, hour, day of month,
+/// month, day of week) or six or seven with seconds first and an optional year last.
+/// Times are in UTC.
+pub fn parse_schedule(expression: &str) -> Result<Schedule, String> {
+    let fields = expression.split_whitespace().count();
+    let expression = if fields == 5 { format!("0 {expression}") } else { expression.to_string() };
+    Schedule::from_str(&expression).map_err(|err| format!("invalid cron expression `{expression}`: {err}"))
+}
+
+/// The scripts that run on a schedule, and how their latest runs went.
+pub struct Scheduler {
+    jobs: Vec<Arc<Job>>,
+}
+
+struct Job {
+    config: JobConfig,
+    schedule: Schedule,
+    running: AtomicBool,        // a run has started and not finished yet
+    runs: Mutex<VecDeque<Run>>, // latest to finish first, at most `history`
+    history: usize,
+}
+
+/// One run of a job, or a tick skipped because the previous run was still going.
+#[derive(Clone, Serialize)]
+struct Run {
+    started: String,
+    duration_ms: f64,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+impl Scheduler {
+    /// Jobs as configured; the config has already checked their schedules and arguments.
+    pub fn new(config: &SchedulerConfig) -> Self {
+        let jobs = config
+            .jobs
+            .iter()
+            .map(|job| {
+                Arc::new(Job {
+                    config: job.clone(),
+                    schedule: parse_schedule(&job.schedule).expect("schedules are checked with the config"),
+                    running: AtomicBool::new(false),
+                    runs: Mutex::new(VecDeque::new()),
+                    history: config.history,
+                })
+            })
+            .collect();
+        Scheduler { jobs }
+    }
+
+    /// Starts a timer for every job on the current runtime. Runs go through the same
+    /// host as HTTP calls, so they share its engines, limits, memo and state store.
+    pub fn start(&self, host: Arc<ScriptHost>) {
+        for job in &self.jobs {
+            if host.script(&job.config.script).is_none() {
+                warn!(job = job.config.name, script = job.config.script, "job runs a script that does not exist (yet)");
+            }
+            info!(job = job.config.name, schedule = job.config.schedule, script = job.config.script, "scheduled");
+            rt::spawn(job.clone().tick(host.clone()));
+        }
+    }
+}
+
+impl Job {
+    /// Waits for each time on the schedule and starts a run, unless the last one is still
+    /// going: then that tick is skipped and recorded as `cancelled`.
+    async fn tick(self: Arc<Self>, host: Arc<ScriptHost>) {
+        for next in self.schedule.upcoming(Utc) {
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            sleep(wait).await;
+
+            if self.running.swap(true, Ordering::AcqRel) {
+                warn!(job = self.config.name, "skipped: the previous run is still going");
+                let error = ScriptError::new(ErrorKind::Cancelled, "skipped: the previous run was still going");
+                self.record(next, 0.0, Outcome::Err { error });
+                continue;
+            }
+            let span = info_span!("job", job = self.config.name, scheduled = %next.to_rfc3339_opts(SecondsFormat::Secs, true));
+            rt::spawn(self.clone().run(host.clone(), next).instrument(span));
+        }
+    }
+
+    async fn run(self: Arc<Self>, host: Arc<ScriptHost>, scheduled: DateTime<Utc>) {
+        let start = Instant::now();
+        let (script, args) = (self.config.script.clone(), self.config.args.clone());
+        let span = tracing::Span::current();
+        let result = web::block(move || {
+            let _entered = span.enter();
+            host.call(&script, Args::from_json(args)?)
+        })
+        .await
+        .unwrap_or_else(|err| Err(ScriptError::new(ErrorKind::Runtime, err.to_string())));
+        let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+        match &result {
+            Ok(value) => info!(result = %value, duration_ms, "job finished"),
+            Err(err) => warn!(kind = %err.kind, error = %err, duration_ms, "job failed"),
+        }
+        self.record(scheduled, duration_ms, result.into());
+        self.running.store(false, Ordering::Release);
+    }
+
+    fn record(&self, started: DateTime<Utc>, duration_ms: f64, outcome: Outcome) {
+        let mut runs = self.runs.lock().unwrap();
+        runs.push_front(Run {
+            started: started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            duration_ms,
+            outcome,
+        });
+        runs.truncate(self.history);
+    }
+}
+
+#[derive(Serialize)]
+struct JobReport {
+    name: String,
+    schedule: String,
+    script: String,
+    running: bool,
+    next_run: Option<String>,
+    runs: Vec<Run>, // latest to finish first
+}
+
+/// Every scheduled job with its next run time and its last runs, latest first.
+#[get("/admin/jobs")]
+pub async fn list(scheduler: Data<Scheduler>) -> impl Responder {
+    let jobs: Vec<JobReport> = scheduler
+        .jobs
+        .iter()
+        .map(|job| JobReport {
+            name: job.config.name.clone(),
+            schedule: job.config.schedule.clone(),
+            script: job.config.script.clone(),
+            running: job.running.load(Ordering::Acquire),
+            next_run: job.schedule.upcoming(Utc).next().map(|next| next.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            runs: job.runs.lock().unwrap().iter().cloned().collect(),
+        })
+        .collect();
+    Json(jobs)
+}
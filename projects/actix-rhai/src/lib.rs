@@ -0,0 +1,23 @@
+//! Everything the script server is built from. The HTTP server itself is `main.rs`;
+//! keeping the rest in a library lets the `rhai-test` runner set up engines and bind
+//! arguments exactly the way the server does.
+
+pub mod arith;
+pub mod auth;
+pub mod batch;
+pub mod config;
+pub mod error;
+pub mod health;
+pub mod jobs;
+pub mod memo;
+pub mod meta;
+pub mod metrics;
+pub mod modules;
+pub mod openapi;
+pub mod params;
+pub mod repl;
+pub mod routes;
+pub mod sandbox;
+pub mod scripts;
+pub mod state;
+pub mod trace;
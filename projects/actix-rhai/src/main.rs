@@ -1,49 +1,229 @@
 use actix_web::{
     HttpServer,
+    HttpRequest,
+    HttpResponse,
     get,
+    middleware::{from_fn, Condition},
+    route,
+    rt::signal,
     App,
-    web::Path,
+    web::{self, Bytes, Data, Json, Path, Query},
     Responder
 };
 
-use rhai::Engine;
+use actix_rhai::auth::{self, Auth};
+use actix_rhai::config::Config;
+use actix_rhai::error::{ErrorKind, ScriptError};
+use actix_rhai::jobs::{self, Scheduler};
+use actix_rhai::memo::{self, Memo};
+use actix_rhai::metrics;
+use actix_rhai::params::{Args, Input};
+use actix_rhai::repl::{self, Repl};
+use actix_rhai::routes::{self, body_args, query_args, run_script, Declared};
+use actix_rhai::scripts::ScriptHost;
+use actix_rhai::state::StateStore;
+use actix_rhai::{batch, health, openapi, trace};
+use futures_util::future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
 
-#[get("/multiply/{num1}/{num2}")]
-async fn multiply(path: Path<(i64, i64)>) -> impl Responder {
-    let (num1, num2) = path.into_inner();
+/// Runs any discovered script; path segments after the script name fill its parameters
+/// in declaration order and query-string values fill them by name.
+#[get("/run/{script}/{args:.*}")]
+async fn run(
+    req: HttpRequest,
+    host: Data<ScriptHost>,
+    path: Path<(String, String)>,
+    query: Query<Vec<(String, String)>>,
+) -> Result<HttpResponse, ScriptError> {
+    let (script, args) = path.into_inner();
 
-    let mut engine = Engine::new();
-    engine.register_fn("num1", move || num1);
-    engine.register_fn("num2", move || num2);
+    let args = Args {
+        positional: args
+            .split('/')
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| Input::Text(arg.to_string()))
+            .collect(),
+        named: query_args(query.into_inner()),
+    };
+    run_script(&req, host, script, args).await
+}
 
-    let result = engine.eval_file::<i64>("src/multiply.rhai".into()).unwrap();
+/// Runs a script with named arguments from the query string and/or a JSON object body.
+#[route("/run/{script}", method = "GET", method = "POST")]
+async fn run_named(
+    req: HttpRequest,
+    host: Data<ScriptHost>,
+    script: Path<String>,
+    query: Query<Vec<(String, String)>>,
+    body: Bytes,
+) -> Result<HttpResponse, ScriptError> {
+    let mut named = query_args(query.into_inner());
+    named.extend(body_args(&body)?);
 
-    format!("{result}")
+    let args = Args {
+        positional: Vec::new(),
+        named,
+    };
+    run_script(&req, host, script.into_inner(), args).await
 }
 
-#[get("/add/{num1}/{num2}")]
-async fn add(path: Path<(i64, i64)>) -> impl Responder {
-    let (num1, num2) = path.into_inner();
-
-    let mut engine = Engine::new();
-    engine.register_fn("num1", move || num1);
-    engine.register_fn("num2", move || num2);
+/// Lists the names of every script that can be called through `/run/{script}`.
+#[get("/scripts")]
+async fn list_scripts(host: Data<ScriptHost>) -> impl Responder {
+    Json(host.names())
+}
 
-    let result = engine.eval_file::<i64>("src/add.rhai".into()).unwrap();
+/// Dumps the persistent state of every script as `{ script: { key: value } }`.
+#[get("/admin/state")]
+async fn export_state(state: Data<StateStore>) -> Result<impl Responder, ScriptError> {
+    let export = web::block(move || state.export(None))
+        .await
+        .map_err(|err| ScriptError::new(ErrorKind::Storage, err.to_string()))??;
+    Ok(Json(export))
+}
 
-    format!("{result}")
+/// Shows the persistent state of a single script as `{ key: value }`.
+#[get("/admin/state/{script}")]
+async fn inspect_state(state: Data<StateStore>, script: Path<String>) -> Result<impl Responder, ScriptError> {
+    let export = web::block(move || state.export(Some(&script)))
+        .await
+        .map_err(|err| ScriptError::new(ErrorKind::Storage, err.to_string()))??;
+    Ok(Json(export.into_values().next().unwrap_or_default()))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    let config = Config::load().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(2);
+    });
+    trace::init(config.debug);
+    let auth = config.auth.keys_file.as_deref().map(|keys_file| {
+        let auth = Auth::load(keys_file, config.auth.audit_log.as_deref()).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(2);
+        });
+        Data::new(auth)
+    });
+    let state = Arc::new(StateStore::open(&config.state_db).map_err(std::io::Error::other)?);
+
+    // One engine and one script cache for the whole server, compiled up front.
+    let memo = Memo::new(config.memo.capacity, Duration::from_secs(config.memo.ttl_secs));
+    let host = Arc::new(
+        ScriptHost::new(
+            config.limits.limits(),
+            config.overflow,
+            config.disabled_symbols.clone(),
+            state.clone(),
+            &config.lib_dir,
+        )
+        .with_memo(memo)
+        .with_debug(config.debug),
+    );
+    host.discover(&config.scripts_dir)?;
+    let _watcher = host.watch(&config.scripts_dir).map_err(std::io::Error::other)?; // keeps hot reloading alive
+    let declared = routes::collect(&host).unwrap_or_else(|problems| {
+        eprintln!("conflicting script routes:");
+        for problem in problems {
+            eprintln!("  - {problem}");
+        }
+        std::process::exit(2);
+    });
+    for Declared { route, script } in &declared {
+        info!(method = route.method, path = route.path, script, "routing");
+    }
+
+    let scheduler = Data::new(Scheduler::new(&config.scheduler));
+    scheduler.start(host.clone());
+
+    let data = Data::from(host.clone());
+    let state = Data::from(state);
+    let repl_enabled = config.repl.enabled;
+    let repl = Data::new(Repl::new(&config.repl));
+    let auth_enabled = auth.is_some();
+    let mut server = HttpServer::new(move || {
         App::new()
-        .service(multiply)
-        .service(add)
-    })
-    .bind(("127.0.0.1", 8080))
-    .unwrap()
-    .run()
-    .await
+        .app_data(data.clone())
+        .app_data(state.clone())
+        .app_data(repl.clone())
+        .app_data(scheduler.clone())
+        .configure(|cfg| {
+            if let Some(auth) = &auth {
+                cfg.app_data(auth.clone());
+            }
+        })
+        .wrap(Condition::new(auth_enabled, from_fn(auth::check)))
+        .wrap(from_fn(metrics::track))
+        .wrap(from_fn(trace::request))
+        .service(run)
+        .service(run_named)
+        .service(batch::batch)
+        .service(openapi::openapi)
+        .service(openapi::docs)
+        .service(list_scripts)
+        .service(health::healthz)
+        .service(health::readyz)
+        .service(metrics::export)
+        .service(export_state)
+        .service(inspect_state)
+        .service(memo::stats)
+        .service(jobs::list)
+        .configure(|cfg| routes::configure(&declared, cfg))
+        .configure(|cfg| {
+            if repl_enabled {
+                cfg.service(repl::session);
+            }
+        })
+    });
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    // Signals are handled below rather than by actix, so shutdown can be logged.
+    let server = server
+        .disable_signals()
+        .shutdown_timeout(config.shutdown_timeout_secs)
+        .bind((config.bind.as_str(), config.port))?
+        .run();
+    info!(bind = config.bind, port = config.port, "listening");
+
+    let handle = server.handle();
+    let deadline = config.shutdown_timeout_secs;
+    let metrics_host = host.clone();
+    // The only way the server stops, so this has finished once the server has.
+    let stopping = actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        info!(deadline_secs = deadline, "shutting down: no new connections, draining requests");
+        let abandoned_before = metrics_host.metrics().abandoned_requests();
+        handle.stop(true).await;
+        abandoned_before
+    });
+    server.await?;
+
+    // Requests still running at the deadline are dropped, not finished.
+    let abandoned = stopping.await.map_or(0, |before| host.metrics().abandoned_requests() - before);
+    if abandoned > 0 {
+        warn!(abandoned, "requests still running at the shutdown deadline were dropped");
+    }
+    info!("stopped");
+    Ok(())
+}
 
+/// Resolves on SIGTERM (what orchestrators send) or Ctrl-C.
+async fn shutdown_signal() {
+    let ctrl_c = Box::pin(signal::ctrl_c());
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let terminate = Box::pin(async move { terminate.recv().await });
+                future::select(ctrl_c, terminate).await;
+                return;
+            }
+            Err(err) => warn!(error = %err, "cannot listen for SIGTERM, only Ctrl-C stops the server gracefully"),
+        }
+    }
+    let _ = ctrl_c.await;
 }
@@ -0,0 +1,157 @@
+use crate::scripts::ScriptHost;
+use actix_web::{
+    get,
+    web::{Data, Json},
+    Responder,
+};
+use lru::LruCache;
+use rhai::Dynamic;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Results of scripts declared `pure`, keyed by the compiled version of the script and
+/// its bound arguments.
+///
+/// Every compilation of a script gets a new version, so a reloaded script never sees
+/// the results of the old one; [`invalidate`](Self::invalidate) also frees them right
+/// away. Entries older than the TTL are treated as missing, and the least recently
+/// used entry makes room once the cache is full.
+pub struct Memo {
+    ttl: Duration,
+    inner: Option<Mutex<Inner>>, // `None` when the capacity is 0: nothing is memoized
+}
+
+struct Inner {
+    entries: LruCache<(u64, String), Entry>, // (script version, arguments)
+    stats: MemoStats,
+}
+
+struct Entry {
+    script: String,
+    value: Dynamic,
+    stored: Instant,
+}
+
+/// What `/admin/memo` reports.
+#[derive(Clone, Default, Serialize)]
+pub struct MemoStats {
+    pub capacity: usize,
+    pub ttl_secs: u64,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub expired: u64,     // entries found but older than the TTL
+    pub evicted: u64,     // entries dropped to make room
+    pub invalidated: u64, // entries dropped because their script was reloaded
+    pub scripts: BTreeMap<String, ScriptStats>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct ScriptStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Memo {
+    /// A cache of at most `capacity` results, each kept for `ttl`.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        let inner = NonZeroUsize::new(capacity).map(|capacity| {
+            Mutex::new(Inner {
+                entries: LruCache::new(capacity),
+                stats: MemoStats::default(),
+            })
+        });
+        Memo { ttl, inner }
+    }
+
+    /// A cache that never stores anything.
+    pub fn disabled() -> Self {
+        Memo::new(0, Duration::ZERO)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// The stored result of `script` (compiled as `version`) for `args`, counting a hit
+    /// or a miss.
+    pub fn get(&self, script: &str, version: u64, args: &str) -> Option<Dynamic> {
+        let mut inner = self.inner.as_ref()?.lock().unwrap();
+        let inner = &mut *inner;
+        let key = (version, args.to_string());
+        let found = match inner.entries.get(&key) {
+            Some(entry) if entry.stored.elapsed() < self.ttl => Some(entry.value.clone()),
+            Some(_) => {
+                inner.entries.pop(&key);
+                inner.stats.expired += 1;
+                None
+            }
+            None => None,
+        };
+        let script = inner.stats.scripts.entry(script.to_string()).or_default();
+        if found.is_some() {
+            inner.stats.hits += 1;
+            script.hits += 1;
+        } else {
+            inner.stats.misses += 1;
+            script.misses += 1;
+        }
+        found
+    }
+
+    pub fn insert(&self, script: &str, version: u64, args: String, value: Dynamic) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let mut inner = inner.lock().unwrap();
+        let entry = Entry {
+            script: script.to_string(),
+            value,
+            stored: Instant::now(),
+        };
+        if let Some((key, _)) = inner.entries.push((version, args.clone()), entry)
+            && key != (version, args)
+        {
+            inner.stats.evicted += 1;
+        }
+    }
+
+    /// Drops every result of the script compiled as `version`.
+    pub fn invalidate(&self, version: u64) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let mut inner = inner.lock().unwrap();
+        let stale: Vec<(u64, String)> =
+            inner.entries.iter().filter(|((v, _), _)| *v == version).map(|(key, _)| key.clone()).collect();
+        for key in &stale {
+            inner.entries.pop(key);
+        }
+        inner.stats.invalidated += stale.len() as u64;
+    }
+
+    pub fn stats(&self) -> MemoStats {
+        let Some(inner) = &self.inner else {
+            return MemoStats::default();
+        };
+        let inner = inner.lock().unwrap();
+        let mut report = inner.stats.clone();
+        report.capacity = inner.entries.cap().get();
+        report.ttl_secs = self.ttl.as_secs();
+        report.entries = inner.entries.len();
+        for (_, entry) in inner.entries.iter() {
+            report.scripts.entry(entry.script.clone()).or_default().entries += 1;
+        }
+        report
+    }
+}
+
+/// Hit and miss counts of the memoized results of pure scripts.
+#[get("/admin/memo")]
+pub async fn stats(host: Data<ScriptHost>) -> impl Responder {
+    Json(host.memo().stats())
+}
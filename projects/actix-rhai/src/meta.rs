@@ -0,0 +1,274 @@
+use crate::arith::Overflow;
+use crate::error::{ErrorKind, ScriptError};
+use crate::params::{self, Input};
+use crate::sandbox::Limits;
+use crate::scripts::RESULT_TYPES;
+use rhai::Dynamic;
+use std::time::Duration;
+
+/// Settings a script declares about itself in a header of `//!` comment lines at the
+/// very top of the file:
+///
+/// ```text
+/// //! description: Scales a number.
+/// //! param num1: int
+/// //! param scale: float = 1.0
+/// //! returns: float
+/// //! overflow: promote
+/// //! sequential: true
+/// //! pure: true
+/// //! max_operations: 10000
+/// //! timeout_ms: 250
+/// //! route: POST /scale/{num1}
+/// ```
+///
+/// The header is plain comments to Rhai, so the script still runs unchanged anywhere.
+#[derive(Debug, Clone, Default)]
+pub struct Meta {
+    pub description: Option<String>,
+    pub params: Vec<Param>,
+    pub returns: Option<&'static str>, // one of `scripts::RESULT_TYPES`
+    pub limits: Limits,
+    pub overflow: Overflow,
+    /// Batched calls to this script must run one at a time and in order, e.g. because
+    /// each one builds on state written by the previous one.
+    pub sequential: bool,
+    /// The result depends on nothing but the arguments (no state, no clock), so it can be
+    /// memoized.
+    pub pure: bool,
+    /// Routes served besides `/run/{script}`, mounted when the server starts.
+    pub routes: Vec<Route>,
+}
+
+/// An HTTP route a script declares with `//! route: <METHOD> <path>`, e.g.
+/// `POST /orders/{id}/total`. Each `{placeholder}` is one path segment and must name a
+/// declared parameter; the other parameters come from the query string or a JSON body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub method: &'static str, // one of `METHODS`
+    pub path: String,
+}
+
+/// Methods a script route can be declared with.
+pub const METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE"];
+
+impl Route {
+    /// Names of the placeholders in the path, in order.
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.segments().filter_map(placeholder)
+    }
+
+    /// Whether a request could match both routes, e.g. `GET /orders/{id}` and
+    /// `GET /orders/latest`: the server could only ever pick one of them.
+    pub fn overlaps(&self, other: &Route) -> bool {
+        self.method == other.method
+            && self.segments().count() == other.segments().count()
+            && self
+                .segments()
+                .zip(other.segments())
+                .all(|(a, b)| a == b || placeholder(a).is_some() || placeholder(b).is_some())
+    }
+
+    fn segments(&self) -> impl Iterator<Item = &str> {
+        self.path.split('/').skip(1)
+    }
+}
+
+fn placeholder(segment: &str) -> Option<&str> {
+    segment.strip_prefix('{')?.strip_suffix('}')
+}
+
+/// A named, typed argument the script expects to find in its scope.
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub ty: ParamType,
+    pub default: Option<Dynamic>, // `None` makes the parameter required
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Int,
+    Float,
+    String,
+    Bool,
+    Array,
+}
+
+impl ParamType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ParamType::Int => "int",
+            ParamType::Float => "float",
+            ParamType::String => "string",
+            ParamType::Bool => "bool",
+            ParamType::Array => "array",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "int" => ParamType::Int,
+            "float" => ParamType::Float,
+            "string" => ParamType::String,
+            "bool" => ParamType::Bool,
+            "array" => ParamType::Array,
+            _ => return None,
+        })
+    }
+}
+
+/// Parses the metadata header of `source`, starting from the server-wide `defaults`.
+pub fn parse(source: &str, defaults: &Meta) -> Result<Meta, ScriptError> {
+    let mut meta = defaults.clone();
+    let mut route_lines = Vec::new(); // checked against the parameters once all are known
+    for (i, line) in source.lines().enumerate() {
+        let Some(entry) = line.trim_start().strip_prefix("//!") else {
+            break;
+        };
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let error = |message: String| {
+            let mut err = ScriptError::new(ErrorKind::Parse, message);
+            err.line = Some(i + 1);
+            err
+        };
+        let (key, value) = entry
+            .split_once(':')
+            .ok_or_else(|| error(format!("expected `key: value` in metadata header, found `{entry}`")))?;
+        let (key, value) = (key.trim(), value.trim());
+        if let Some(name) = key.strip_prefix("param ") {
+            let param = parse_param(name.trim(), value).map_err(error)?;
+            if meta.params.iter().any(|p| p.name == param.name) {
+                return Err(error(format!("parameter `{}` declared twice", param.name)));
+            }
+            meta.params.push(param);
+            continue;
+        }
+        if key == "description" {
+            // Several description lines read as one paragraph.
+            meta.description = Some(match meta.description.take() {
+                Some(description) => format!("{description} {value}"),
+                None => value.to_string(),
+            });
+            continue;
+        }
+        if key == "returns" {
+            let ty = RESULT_TYPES.iter().find(|ty| **ty == value);
+            meta.returns = Some(*ty.ok_or_else(|| {
+                error(format!("unknown result type `{value}` (expected one of {})", RESULT_TYPES.join(", ")))
+            })?);
+            continue;
+        }
+        if key == "route" {
+            let route = parse_route(value).map_err(error)?;
+            if let Some(other) = meta.routes.iter().find(|other| other.overlaps(&route)) {
+                return Err(error(format!("route `{} {}` overlaps `{} {}`", route.method, route.path, other.method, other.path)));
+            }
+            meta.routes.push(route);
+            route_lines.push(i + 1);
+            continue;
+        }
+        if key == "overflow" {
+            meta.overflow = Overflow::parse(value)
+                .ok_or_else(|| error(format!("unknown overflow policy `{value}` (expected reject, saturate or promote)")))?;
+            continue;
+        }
+        if key == "sequential" || key == "pure" {
+            let flag = value
+                .parse()
+                .map_err(|_| error(format!("invalid value for `{key}`: expected true or false, found `{value}`")))?;
+            if key == "pure" {
+                meta.pure = flag;
+            } else {
+                meta.sequential = flag;
+            }
+            continue;
+        }
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|err| error(format!("invalid value for `{key}`: {err}")))
+        };
+        let limits = &mut meta.limits;
+        match key {
+            "max_operations" => limits.max_operations = number()?,
+            "max_call_levels" => limits.max_call_levels = number()? as usize,
+            "max_string_size" => limits.max_string_size = number()? as usize,
+            "max_array_size" => limits.max_array_size = number()? as usize,
+            "max_map_size" => limits.max_map_size = number()? as usize,
+            "timeout_ms" => limits.timeout = Duration::from_millis(number()?),
+            _ => return Err(error(format!("unknown metadata key `{key}`"))),
+        }
+    }
+    for (route, line) in meta.routes.iter().zip(route_lines) {
+        if let Some(name) = route.placeholders().find(|name| !meta.params.iter().any(|p| p.name == *name)) {
+            let mut err = ScriptError::new(ErrorKind::Parse, format!("route placeholder `{{{name}}}` is not a declared parameter"));
+            err.line = Some(line);
+            return Err(err);
+        }
+    }
+    Ok(meta)
+}
+
+/// Parses the `<METHOD> <path>` part of a `route: ...` line.
+fn parse_route(value: &str) -> Result<Route, String> {
+    let (method, path) = value
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("expected `<METHOD> <path>` for `route`, found `{value}`"))?;
+    let method = method.to_ascii_uppercase();
+    let method = *METHODS
+        .iter()
+        .find(|known| **known == method)
+        .ok_or_else(|| format!("unsupported method `{method}` (expected one of {})", METHODS.join(", ")))?;
+    let path = path.trim();
+    let Some(rest) = path.strip_prefix('/') else {
+        return Err(format!("route path `{path}` must start with `/`"));
+    };
+    let mut seen = Vec::new();
+    for segment in rest.split('/') {
+        let literal = |c: char| c.is_ascii_alphanumeric() || "-_.~".contains(c);
+        match placeholder(segment) {
+            Some(name) if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                if seen.contains(&name) {
+                    return Err(format!("placeholder `{{{name}}}` appears twice in `{path}`"));
+                }
+                seen.push(name);
+            }
+            None if !segment.is_empty() && segment.chars().all(literal) => {}
+            None if segment.is_empty() && rest.is_empty() => {} // the root, `/`
+            _ => return Err(format!("invalid segment `{segment}` in route path `{path}`")),
+        }
+    }
+    Ok(Route {
+        method,
+        path: path.to_string(),
+    })
+}
+
+/// Parses the `<type> [= <default>]` part of a `param <name>: ...` line.
+fn parse_param(name: &str, value: &str) -> Result<Param, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid parameter name `{name}`"));
+    }
+    let (ty, default) = match value.split_once('=') {
+        Some((ty, default)) => (ty.trim(), Some(default.trim())),
+        None => (value, None),
+    };
+    let ty = ParamType::parse(ty).ok_or_else(|| format!("unknown type `{ty}` for parameter `{name}`"))?;
+    let default = default
+        .map(|default| {
+            // Defaults are written like query-string values, optionally in quotes.
+            let default = default.strip_prefix('"').and_then(|d| d.strip_suffix('"')).unwrap_or(default);
+            params::coerce(ty, &Input::Text(default.to_string()))
+                .map_err(|err| format!("invalid default for `{name}`: {err}"))
+        })
+        .transpose()?;
+    Ok(Param {
+        name: name.to_string(),
+        ty,
+        default,
+    })
+}
@@ -0,0 +1,230 @@
+use crate::error::ErrorKind;
+use crate::scripts::ScriptHost;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    middleware::Next,
+    web::Data,
+    HttpMessage, HttpResponse, Responder,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Counters for the whole server, updated with plain atomic increments so that keeping
+/// them costs next to nothing; all the formatting happens when `/metrics` is scraped.
+#[derive(Default)]
+pub struct Metrics {
+    active_requests: AtomicI64,
+    abandoned_requests: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    unknown_script_calls: AtomicU64,
+    scripts: RwLock<BTreeMap<String, Arc<ScriptMetrics>>>,
+}
+
+/// Counters for one script.
+#[derive(Default)]
+pub struct ScriptMetrics {
+    invocations: AtomicU64,
+    errors: [AtomicU64; ErrorKind::ALL.len()],
+    pub eval: Histogram,    // time spent evaluating the script
+    pub request: Histogram, // time from the request arriving to the response leaving
+}
+
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()], // not cumulative; summed up when rendered
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, script: &str) {
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{script=\"{script}\",le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64();
+        let _ = writeln!(out, "{name}_bucket{{script=\"{script}\",le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum{{script=\"{script}\"}} {sum}");
+        let _ = writeln!(out, "{name}_count{{script=\"{script}\"}} {count}");
+    }
+}
+
+impl Metrics {
+    /// Starts tracking `name`. Only registered scripts get their own series, so calls to
+    /// made-up names can't grow the output without bound.
+    pub fn add_script(&self, name: &str) {
+        self.scripts.write().unwrap().entry(name.to_string()).or_default();
+    }
+
+    pub fn script(&self, name: &str) -> Option<Arc<ScriptMetrics>> {
+        self.scripts.read().unwrap().get(name).cloned()
+    }
+
+    /// Counts one call of `name` and, if it failed, the kind of error.
+    pub fn record_call(&self, name: &str, error: Option<ErrorKind>) {
+        let Some(script) = self.script(name) else {
+            self.unknown_script_calls.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        script.invocations.fetch_add(1, Ordering::Relaxed);
+        if let Some(kind) = error {
+            let index = ErrorKind::ALL.iter().position(|known| *known == kind).expect("every kind is listed");
+            script.errors[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Requests being handled right now.
+    pub fn active_requests(&self) -> i64 {
+        self.active_requests.load(Ordering::Relaxed)
+    }
+
+    /// Requests dropped before their response was ready, because the client went away or
+    /// the shutdown deadline passed.
+    pub fn abandoned_requests(&self) -> u64 {
+        self.abandoned_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Everything in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let scripts = self.scripts.read().unwrap().clone();
+        let scripts: Vec<(String, Arc<ScriptMetrics>)> =
+            scripts.into_iter().map(|(name, script)| (escape(&name), script)).collect();
+
+        header(&mut out, "rhai_active_requests", "gauge", "Requests currently being handled.");
+        let _ = writeln!(out, "rhai_active_requests {}", self.active_requests.load(Ordering::Relaxed));
+        header(&mut out, "rhai_abandoned_requests_total", "counter", "Requests dropped before their response was ready.");
+        let _ = writeln!(out, "rhai_abandoned_requests_total {}", self.abandoned_requests.load(Ordering::Relaxed));
+
+        header(&mut out, "rhai_script_cache_hits_total", "counter", "Script calls served from the compiled cache.");
+        let _ = writeln!(out, "rhai_script_cache_hits_total {}", self.cache_hits.load(Ordering::Relaxed));
+        header(&mut out, "rhai_script_cache_misses_total", "counter", "Script calls that had to compile the file first.");
+        let _ = writeln!(out, "rhai_script_cache_misses_total {}", self.cache_misses.load(Ordering::Relaxed));
+
+        header(&mut out, "rhai_unknown_script_calls_total", "counter", "Calls naming a script that does not exist.");
+        let _ = writeln!(out, "rhai_unknown_script_calls_total {}", self.unknown_script_calls.load(Ordering::Relaxed));
+
+        header(&mut out, "rhai_script_invocations_total", "counter", "Calls of each script.");
+        for (name, script) in &scripts {
+            let invocations = script.invocations.load(Ordering::Relaxed);
+            let _ = writeln!(out, "rhai_script_invocations_total{{script=\"{name}\"}} {invocations}");
+        }
+
+        header(&mut out, "rhai_script_errors_total", "counter", "Failed calls of each script by error kind.");
+        for (name, script) in &scripts {
+            for (kind, errors) in ErrorKind::ALL.iter().zip(&script.errors) {
+                let errors = errors.load(Ordering::Relaxed);
+                if errors > 0 {
+                    let _ = writeln!(out, "rhai_script_errors_total{{script=\"{name}\",kind=\"{kind}\"}} {errors}");
+                }
+            }
+        }
+
+        let name = "rhai_script_eval_duration_seconds";
+        header(&mut out, name, "histogram", "Time spent evaluating each script.");
+        for (script, metrics) in &scripts {
+            metrics.eval.render(&mut out, name, script);
+        }
+
+        let name = "rhai_script_request_duration_seconds";
+        header(&mut out, name, "histogram", "Total time of requests running one script, including evaluation.");
+        for (script, metrics) in &scripts {
+            metrics.request.render(&mut out, name, script);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, ty: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {ty}");
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Keeps a request counted as active until dropped, which also covers requests whose
+/// client goes away before the response is ready; those, and requests cut off by the
+/// shutdown deadline, are counted as abandoned unless [`finish`](Self::finish)ed first.
+struct InFlight<'a> {
+    metrics: &'a Metrics,
+    finished: bool,
+}
+
+impl<'a> InFlight<'a> {
+    fn start(metrics: &'a Metrics) -> Self {
+        metrics.active_requests.fetch_add(1, Ordering::Relaxed);
+        InFlight { metrics, finished: false }
+    }
+
+    fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.metrics.active_requests.fetch_sub(1, Ordering::Relaxed);
+        if !self.finished {
+            self.metrics.abandoned_requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Marks a request as a call of one script, so [`track`] can attribute its total time.
+pub struct ScriptCall(pub String);
+
+/// Middleware counting in-flight requests and timing those that call a script.
+pub async fn track(
+    host: Data<ScriptHost>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = host.metrics();
+    let start = Instant::now();
+    let in_flight = InFlight::start(metrics);
+    let response = next.call(req).await;
+    in_flight.finish();
+
+    if let Ok(response) = &response
+        && let Some(ScriptCall(name)) = response.request().extensions().get::<ScriptCall>()
+        && let Some(script) = metrics.script(name)
+    {
+        script.request.observe(start.elapsed());
+    }
+    response
+}
+
+#[get("/metrics")]
+pub async fn export(host: Data<ScriptHost>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(host.metrics().render())
+}
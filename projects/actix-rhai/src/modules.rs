@@ -0,0 +1,117 @@
+use crate::meta::Meta;
+use crate::scripts::{key, Cache, Compiled};
+use rhai::module_resolvers::ModuleResolver;
+use rhai::{Engine, EvalAltResult, Module, Position, Scope, Shared, AST};
+use std::cell::RefCell;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Resolves `import "name" as m;` to `<lib dir>/name.rhai`.
+///
+/// Library files are compiled into the same cache as the scripts themselves and
+/// evaluated into a module the first time anything imports them. Scripts are compiled
+/// with every import resolved up front (see `ScriptHost::load`), so a missing or
+/// circular import shows up when the script is loaded, not when it is called.
+#[derive(Clone)]
+pub struct LibResolver {
+    dir: PathBuf,
+    cache: Arc<Cache>,
+}
+
+thread_local! {
+    /// Library files currently being evaluated on this thread, outermost first.
+    static IMPORTING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+impl LibResolver {
+    pub fn new(dir: impl Into<PathBuf>, cache: Arc<Cache>) -> Self {
+        LibResolver {
+            dir: dir.into(),
+            cache,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether `path` is a file inside the library directory.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(key(&self.dir))
+    }
+
+    /// File for an import path. Only plain relative paths are accepted, so an import
+    /// can't reach outside the library directory.
+    fn file(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path);
+        if !relative.components().all(|part| matches!(part, Component::Normal(_))) {
+            return None;
+        }
+        Some(key(&self.dir.join(relative).with_extension("rhai")))
+    }
+
+    /// The cached entry for an import path, compiling the file on first use.
+    fn compiled(&self, engine: &Engine, path: &str, pos: Position) -> Result<Arc<Compiled>, Box<EvalAltResult>> {
+        let file = self.file(path).ok_or_else(|| EvalAltResult::ErrorModuleNotFound(path.into(), pos))?;
+        if let Some(compiled) = self.cache.read().unwrap().get(&file) {
+            return Ok(compiled.clone());
+        }
+        let source = std::fs::read_to_string(&file).map_err(|_| EvalAltResult::ErrorModuleNotFound(path.into(), pos))?;
+        let ast = engine
+            .compile(&source)
+            .map_err(|err| EvalAltResult::ErrorInModule(path.into(), err.into(), pos))?;
+        Ok(self.insert(file, ast, None))
+    }
+
+    fn insert(&self, file: PathBuf, ast: AST, module: Option<Shared<Module>>) -> Arc<Compiled> {
+        let compiled = Arc::new(Compiled::new(ast, Meta::default(), module));
+        self.cache.write().unwrap().insert(file, compiled.clone());
+        compiled
+    }
+}
+
+impl ModuleResolver for LibResolver {
+    fn resolve(
+        &self,
+        engine: &Engine,
+        _source: Option<&str>,
+        path: &str,
+        pos: Position,
+    ) -> Result<Shared<Module>, Box<EvalAltResult>> {
+        let compiled = self.compiled(engine, path, pos)?;
+        if let Some(module) = &compiled.module {
+            return Ok(module.clone());
+        }
+
+        let cycle = IMPORTING.with_borrow(|importing| {
+            let start = importing.iter().position(|name| name == path)?;
+            let mut cycle = importing[start..].to_vec();
+            cycle.push(path.to_string());
+            Some(cycle.join(" -> "))
+        });
+        if let Some(cycle) = cycle {
+            return Err(EvalAltResult::ErrorRuntime(format!("circular import: {cycle}").into(), pos).into());
+        }
+
+        IMPORTING.with_borrow_mut(|importing| importing.push(path.to_string()));
+        let module = Module::eval_ast_as_new(Scope::new(), &compiled.ast, engine);
+        IMPORTING.with_borrow_mut(|importing| importing.pop());
+
+        let module: Shared<Module> = module
+            .map_err(|err| EvalAltResult::ErrorInModule(path.into(), err, pos))?
+            .into();
+        let file = self.file(path).expect("path was accepted above");
+        self.insert(file, compiled.ast.clone(), Some(module.clone()));
+        Ok(module)
+    }
+
+    fn resolve_ast(
+        &self,
+        engine: &Engine,
+        _source: Option<&str>,
+        path: &str,
+        pos: Position,
+    ) -> Option<Result<AST, Box<EvalAltResult>>> {
+        Some(self.compiled(engine, path, pos).map(|compiled| compiled.ast.clone()))
+    }
+}
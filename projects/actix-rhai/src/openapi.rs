@@ -0,0 +1,260 @@
+use crate::error::ErrorKind;
+use crate::meta::{Meta, Param, ParamType, Route};
+use crate::scripts::ScriptHost;
+use actix_web::{get, web::Data, HttpResponse, Responder};
+use serde_json::{json, Map, Value};
+
+/// Self-contained page that renders `/openapi.json`, so the docs work without
+/// reaching any CDN.
+const DOCS_PAGE: &str = include_str!("docs.html");
+
+/// OpenAPI 3 description of every script route, rebuilt on each request so it always
+/// matches the scripts currently loaded.
+#[get("/openapi.json")]
+pub async fn openapi(host: Data<ScriptHost>) -> impl Responder {
+    HttpResponse::Ok().json(document(&host))
+}
+
+#[get("/docs")]
+pub async fn docs() -> impl Responder {
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(DOCS_PAGE)
+}
+
+fn document(host: &ScriptHost) -> Value {
+    let mut paths = Map::new();
+    for name in host.names() {
+        // Scripts that don't compile have no parameters to describe; `/scripts` still
+        // lists them and calling them reports the error.
+        let Some(Ok(script)) = host.script(&name) else {
+            continue;
+        };
+        let meta = &script.meta;
+
+        let named = json!({
+            "get": operation(&name, meta, "query", "by name in the query string"),
+            "post": post_operation(&name, meta),
+        });
+        paths.insert(format!("/run/{name}"), named);
+
+        if !meta.params.is_empty() {
+            let segments: Vec<String> = meta.params.iter().map(|p| format!("{{{}}}", p.name)).collect();
+            let positional = json!({
+                "get": operation(&name, meta, "path", "as path segments in declaration order"),
+            });
+            paths.insert(format!("/run/{name}/{}", segments.join("/")), positional);
+        }
+
+        for (i, route) in meta.routes.iter().enumerate() {
+            let item = paths.entry(route.path.clone()).or_insert_with(|| json!({}));
+            item[route.method.to_ascii_lowercase()] = route_operation(&name, meta, route, i + 1);
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "actix-rhai scripts",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "ScriptError": error_schema(),
+            },
+        },
+    })
+}
+
+fn operation(name: &str, meta: &Meta, location: &str, how: &str) -> Value {
+    let parameters: Vec<Value> = meta
+        .params
+        .iter()
+        .map(|param| {
+            let mut schema = type_schema(param.ty);
+            if let Some(default) = &param.default {
+                schema["default"] = serde_json::to_value(default).unwrap_or(Value::Null);
+            }
+            json!({
+                "name": param.name,
+                "in": location,
+                "required": location == "path" || param.default.is_none(),
+                "schema": schema,
+            })
+        })
+        .collect();
+    json!({
+        "operationId": format!("{name}_{location}"),
+        "summary": meta.description.clone().unwrap_or_else(|| format!("Runs `{name}`.")),
+        "description": format!("Arguments are passed {how}."),
+        "tags": [name],
+        "parameters": parameters,
+        "responses": responses(meta),
+    })
+}
+
+/// A route declared in the script's header: placeholders in the path, everything else
+/// in the query string or, for methods with a body, a JSON object body.
+fn route_operation(name: &str, meta: &Meta, route: &Route, n: usize) -> Value {
+    let in_path = |param: &Param| route.placeholders().any(|placeholder| placeholder == param.name);
+    let has_body = !matches!(route.method, "GET" | "DELETE");
+    let mut parameters = Vec::new();
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for param in &meta.params {
+        let mut schema = type_schema(param.ty);
+        if let Some(default) = &param.default {
+            schema["default"] = serde_json::to_value(default).unwrap_or(Value::Null);
+        }
+        if in_path(param) {
+            parameters.push(json!({ "name": param.name, "in": "path", "required": true, "schema": schema }));
+        } else if has_body {
+            properties.insert(param.name.clone(), schema);
+            if param.default.is_none() {
+                required.push(param.name.clone());
+            }
+        } else {
+            let required = param.default.is_none();
+            parameters.push(json!({ "name": param.name, "in": "query", "required": required, "schema": schema }));
+        }
+    }
+
+    let mut operation = json!({
+        "operationId": format!("{name}_route{n}"),
+        "summary": meta.description.clone().unwrap_or_else(|| format!("Runs `{name}`.")),
+        "description": format!("Route declared by `{name}`."),
+        "tags": [name],
+        "parameters": parameters,
+        "responses": responses(meta),
+    });
+    if has_body && !properties.is_empty() {
+        operation["requestBody"] = json!({
+            "required": !required.is_empty(),
+            "content": {
+                "application/json": {
+                    "schema": {
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                        "additionalProperties": false,
+                    },
+                },
+            },
+        });
+    }
+    operation
+}
+
+fn post_operation(name: &str, meta: &Meta) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for param in &meta.params {
+        properties.insert(param.name.clone(), type_schema(param.ty));
+        if param.default.is_none() {
+            required.push(param.name.clone());
+        }
+    }
+    json!({
+        "operationId": format!("{name}_body"),
+        "summary": meta.description.clone().unwrap_or_else(|| format!("Runs `{name}`.")),
+        "description": "Arguments are passed by name in a JSON object body.",
+        "tags": [name],
+        "requestBody": {
+            "required": !required.is_empty(),
+            "content": {
+                "application/json": {
+                    "schema": {
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                        "additionalProperties": meta.params.is_empty(),
+                    },
+                },
+            },
+        },
+        "responses": responses(meta),
+    })
+}
+
+fn responses(meta: &Meta) -> Value {
+    let result = match meta.returns {
+        Some(ty) => result_schema(ty),
+        None => json!({}),
+    };
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/ScriptError" } } },
+        })
+    };
+    let mut headers = json!({
+        "X-Result-Type": {
+            "description": "Type of the result, e.g. `int`, or `decimal` after an overflow was promoted.",
+            "schema": { "type": "string" },
+        },
+    });
+    if meta.pure {
+        headers["X-Cache"] = json!({
+            "description": "`hit` if the result was memoized, `miss` if it was just computed.",
+            "schema": { "type": "string", "enum": ["hit", "miss"] },
+        });
+    }
+    json!({
+        "200": {
+            "description": "The script's result, as text.",
+            "headers": headers,
+            "content": { "text/plain": { "schema": result } },
+        },
+        "400": error("Invalid arguments; `fields` says what is wrong with each."),
+        "401": error("Missing or unknown credentials, when authentication is enabled."),
+        "403": error("The credentials may not run this script."),
+        "404": error("No such script."),
+        "422": error("The script exceeded a resource limit or overflowed."),
+        "429": error("The credentials have used up their rate limit; see `Retry-After`."),
+        "500": error("The script failed to compile, could not import a module, raised an error, or returned another type than it declares."),
+        "503": error("The script ran past its timeout."),
+    })
+}
+
+fn type_schema(ty: ParamType) -> Value {
+    match ty {
+        ParamType::Int => json!({ "type": "integer", "format": "int64" }),
+        ParamType::Float => json!({ "type": "number", "format": "double" }),
+        ParamType::String => json!({ "type": "string" }),
+        ParamType::Bool => json!({ "type": "boolean" }),
+        ParamType::Array => json!({ "type": "array", "items": {} }),
+    }
+}
+
+fn result_schema(ty: &str) -> Value {
+    match ty {
+        "int" => type_schema(ParamType::Int),
+        "float" => type_schema(ParamType::Float),
+        "decimal" => json!({ "type": "string", "format": "decimal" }),
+        "bool" => type_schema(ParamType::Bool),
+        "array" => type_schema(ParamType::Array),
+        "map" => json!({ "type": "object" }),
+        _ => json!({ "type": "string" }),
+    }
+}
+
+fn error_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["kind", "message"],
+        "properties": {
+            "kind": { "type": "string", "enum": ErrorKind::ALL },
+            "message": { "type": "string" },
+            "line": { "type": "integer", "nullable": true },
+            "column": { "type": "integer", "nullable": true },
+            "fields": {
+                "type": "object",
+                "additionalProperties": { "type": "string" },
+                "description": "Present for `invalid_argument`: what is wrong with each parameter.",
+            },
+            "retry_after": {
+                "type": "integer",
+                "description": "Present for `rate_limited`: seconds until the next request is allowed.",
+            },
+        },
+    })
+}
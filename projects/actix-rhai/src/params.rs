@@ -0,0 +1,161 @@
+use crate::error::{ErrorKind, ScriptError};
+use crate::meta::{Param, ParamType};
+use rhai::{Dynamic, Scope};
+use serde_json::Value;
+use std::collections::btree_map::{BTreeMap, Entry};
+
+/// A raw argument value as it arrived in the request.
+#[derive(Debug, Clone)]
+pub enum Input {
+    /// From the path or the query string; parsed according to the parameter type.
+    Text(String),
+    /// From a JSON body; must already have the right shape (strings are parsed like text).
+    Json(Value),
+}
+
+/// Arguments collected from a request, before they are checked against the script's
+/// declared parameters.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub positional: Vec<Input>, // matched to parameters in declaration order
+    pub named: Vec<(String, Input)>,
+}
+
+impl Args {
+    /// Arguments given as JSON: an array is positional (`[1, 2]`), an object is named
+    /// (`{"num1": 1, "num2": 2}`) and `null` is none at all.
+    pub fn from_json(args: Value) -> Result<Args, ScriptError> {
+        match args {
+            Value::Null => Ok(Args::default()),
+            Value::Array(values) => Ok(Args {
+                positional: values.into_iter().map(Input::Json).collect(),
+                named: Vec::new(),
+            }),
+            Value::Object(values) => Ok(Args {
+                positional: Vec::new(),
+                named: values.into_iter().map(|(name, value)| (name, Input::Json(value))).collect(),
+            }),
+            other => Err(ScriptError::new(
+                ErrorKind::InvalidArgument,
+                format!("`args` must be an array or an object, found {other}"),
+            )),
+        }
+    }
+}
+
+/// Converts `input` to a value of type `ty`, or explains why it can't be.
+pub fn coerce(ty: ParamType, input: &Input) -> Result<Dynamic, String> {
+    let expected = || format!("expected {}, found {}", ty.as_str(), describe(input));
+    match input {
+        Input::Text(text) => match ty {
+            ParamType::Int => text.parse::<i64>().map(Dynamic::from_int).map_err(|_| expected()),
+            ParamType::Float => text.parse::<f64>().map(Dynamic::from_float).map_err(|_| expected()),
+            ParamType::String => Ok(text.clone().into()),
+            ParamType::Bool => text.parse::<bool>().map(Dynamic::from_bool).map_err(|_| expected()),
+            ParamType::Array => match serde_json::from_str(text) {
+                Ok(value @ Value::Array(_)) => coerce(ty, &Input::Json(value)),
+                _ => Err(expected()),
+            },
+        },
+        Input::Json(Value::String(text)) if ty != ParamType::String => coerce(ty, &Input::Text(text.clone())),
+        Input::Json(value) => match (ty, value) {
+            (ParamType::Int, Value::Number(n)) => n.as_i64().map(Dynamic::from_int).ok_or_else(expected),
+            (ParamType::Float, Value::Number(n)) => n.as_f64().map(Dynamic::from_float).ok_or_else(expected),
+            (ParamType::String, Value::String(s)) => Ok(s.clone().into()),
+            (ParamType::Bool, Value::Bool(b)) => Ok(Dynamic::from_bool(*b)),
+            (ParamType::Array, Value::Array(_)) => rhai::serde::to_dynamic(value).map_err(|err| err.to_string()),
+            _ => Err(expected()),
+        },
+    }
+}
+
+fn describe(input: &Input) -> String {
+    match input {
+        Input::Text(text) => format!("`{text}`"),
+        Input::Json(value) => value.to_string(),
+    }
+}
+
+/// Builds the scope a script runs in from the request's arguments.
+///
+/// Every problem is collected into one `invalid_argument` error keyed by field name, so
+/// a caller sees everything that is wrong with a request at once.
+///
+/// Scripts that declare no parameters get the old behaviour: positional arguments become
+/// the integers `num1`, `num2`, ... and named arguments are passed through untyped.
+pub fn bind(params: &[Param], args: Args) -> Result<Scope<'static>, ScriptError> {
+    let mut scope = Scope::new();
+    let mut errors = BTreeMap::new();
+
+    if params.is_empty() {
+        for (i, arg) in args.positional.iter().enumerate() {
+            let name = format!("num{}", i + 1);
+            match coerce(ParamType::Int, arg) {
+                Ok(value) => {
+                    scope.push_dynamic(name, value);
+                }
+                Err(err) => {
+                    errors.insert(name, err);
+                }
+            }
+        }
+        for (name, input) in args.named {
+            let value = match input {
+                Input::Text(text) => text.into(),
+                Input::Json(value) => rhai::serde::to_dynamic(value).unwrap_or_default(),
+            };
+            scope.push_dynamic(name, value);
+        }
+        return finish(scope, errors);
+    }
+
+    let mut given: BTreeMap<String, Input> = BTreeMap::new();
+    if args.positional.len() > params.len() {
+        errors.insert(
+            "path".into(),
+            format!("expected at most {} path arguments, found {}", params.len(), args.positional.len()),
+        );
+    }
+    for (param, arg) in params.iter().zip(args.positional) {
+        given.insert(param.name.clone(), arg);
+    }
+    for (name, input) in args.named {
+        if !params.iter().any(|param| param.name == name) {
+            errors.insert(name, "unknown parameter".into());
+            continue;
+        }
+        match given.entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert(input);
+            }
+            Entry::Occupied(entry) => {
+                errors.insert(entry.key().clone(), "given more than once".into());
+            }
+        }
+    }
+
+    for param in params {
+        let value = match (given.get(&param.name), &param.default) {
+            (Some(input), _) => coerce(param.ty, input),
+            (None, Some(default)) => Ok(default.clone()),
+            (None, None) => Err("required".into()),
+        };
+        match value {
+            Ok(value) => {
+                scope.push_dynamic(param.name.clone(), value);
+            }
+            Err(err) => {
+                errors.entry(param.name.clone()).or_insert(err);
+            }
+        }
+    }
+    finish(scope, errors)
+}
+
+fn finish(scope: Scope<'static>, errors: BTreeMap<String, String>) -> Result<Scope<'static>, ScriptError> {
+    if errors.is_empty() {
+        Ok(scope)
+    } else {
+        Err(ScriptError::invalid_arguments(errors))
+    }
+}
@@ -0,0 +1,199 @@
+use crate::batch::Outcome;
+use crate::config::ReplConfig;
+use crate::error::{ErrorKind, ScriptError};
+use crate::scripts::ScriptHost;
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, CloseCode, CloseReason, Codec, Frame, Message};
+use actix_web::{
+    body::BodyStream,
+    get,
+    rt::time::timeout,
+    web::{self, Bytes, BytesMut, Data, Payload},
+    HttpRequest, HttpResponse,
+};
+use futures_util::{stream, StreamExt};
+use rhai::{Scope, AST};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+/// Open REPL sessions and the limits on them; shared by every worker.
+pub struct Repl {
+    sessions: AtomicUsize,
+    max_sessions: usize,
+    idle_timeout: Duration,
+}
+
+impl Repl {
+    pub fn new(config: &ReplConfig) -> Self {
+        Repl {
+            sessions: AtomicUsize::new(0),
+            max_sessions: config.max_sessions,
+            idle_timeout: Duration::from_secs(config.idle_timeout_secs),
+        }
+    }
+}
+
+/// Holds one of the `max_sessions` slots until the session ends.
+struct Slot(Data<Repl>);
+
+impl Slot {
+    fn claim(repl: Data<Repl>) -> Option<Self> {
+        repl.sessions
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| (open < repl.max_sessions).then_some(open + 1))
+            .ok()?;
+        Some(Slot(repl))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.sessions.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Opens a REPL session over a WebSocket.
+///
+/// Each text frame is evaluated as Rhai against a scope kept for the whole connection,
+/// so variables and functions defined by one frame are there for the next. Every frame
+/// is answered with the same JSON as one `/batch` result: `{"result": .., "type": ..}`
+/// or `{"error": {..}}`. A session that sends nothing for the idle timeout is closed.
+///
+/// Only mounted when `repl.enabled` is set in the config.
+#[get("/repl")]
+pub async fn session(
+    req: HttpRequest,
+    payload: Payload,
+    host: Data<ScriptHost>,
+    repl: Data<Repl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut response = ws::handshake(req.head())?;
+    let idle_timeout = repl.idle_timeout;
+    let Some(slot) = Slot::claim(repl) else {
+        let error = ScriptError::new(ErrorKind::LimitExceeded, "too many open REPL sessions, try again later");
+        return Ok(HttpResponse::ServiceUnavailable().json(error));
+    };
+
+    let session = Session {
+        host,
+        payload,
+        codec: Codec::new(),
+        buf: BytesMut::new(),
+        scope: Scope::new(),
+        functions: AST::empty(),
+        idle_timeout,
+        closed: false,
+        _slot: slot,
+    };
+    let frames = stream::unfold(session, Session::next);
+    let response = response.message_body(BodyStream::new(frames))?;
+    Ok(HttpResponse::from(response.map_into_boxed_body()))
+}
+
+struct Session {
+    host: Data<ScriptHost>,
+    payload: Payload,
+    codec: Codec,
+    buf: BytesMut, // received bytes not yet decoded into a frame
+    scope: Scope<'static>,
+    functions: AST,
+    idle_timeout: Duration,
+    closed: bool,
+    _slot: Slot,
+}
+
+impl Session {
+    /// Waits for the next frame that needs an answer and returns the encoded answer.
+    async fn next(mut self) -> Option<(Result<Bytes, actix_web::Error>, Self)> {
+        if self.closed {
+            return None;
+        }
+        loop {
+            match self.codec.decode(&mut self.buf) {
+                Ok(Some(frame)) => match self.answer(frame).await {
+                    Some(message) => return Some((Ok(self.encode(message)), self)),
+                    None => continue,
+                },
+                Ok(None) => {}
+                Err(err) => {
+                    let message = self.close(CloseCode::Protocol, err.to_string());
+                    return Some((Ok(self.encode(message)), self));
+                }
+            }
+            match timeout(self.idle_timeout, self.payload.next()).await {
+                Ok(Some(Ok(bytes))) => self.buf.extend_from_slice(&bytes),
+                Ok(Some(Err(_)) | None) => return None, // the client went away
+                Err(_) => {
+                    let message = self.close(CloseCode::Normal, "idle timeout".into());
+                    return Some((Ok(self.encode(message)), self));
+                }
+            }
+        }
+    }
+
+    async fn answer(&mut self, frame: Frame) -> Option<Message> {
+        let reply = |outcome: Outcome| Message::Text(serde_json::to_string(&outcome).unwrap_or_default().into());
+        match frame {
+            Frame::Text(code) => {
+                let outcome = match String::from_utf8(code.to_vec()) {
+                    Ok(code) => self.eval(code).await,
+                    Err(_) => Outcome::Err {
+                        error: ScriptError::new(ErrorKind::InvalidArgument, "input is not valid UTF-8"),
+                    },
+                };
+                Some(reply(outcome))
+            }
+            Frame::Binary(_) | Frame::Continuation(_) => Some(reply(Outcome::Err {
+                error: ScriptError::new(ErrorKind::InvalidArgument, "send Rhai code as a single text frame"),
+            })),
+            Frame::Ping(bytes) => Some(Message::Pong(bytes)),
+            Frame::Pong(_) => None,
+            Frame::Close(reason) => {
+                self.closed = true;
+                Some(Message::Close(reason))
+            }
+        }
+    }
+
+    async fn eval(&mut self, code: String) -> Outcome {
+        let host = self.host.clone();
+        let mut scope = std::mem::take(&mut self.scope);
+        let mut functions = std::mem::take(&mut self.functions);
+        let evaluated = web::block(move || {
+            let result = host.eval_repl(&mut scope, &mut functions, &code);
+            (scope, functions, result)
+        })
+        .await;
+        match evaluated {
+            Ok((scope, functions, result)) => {
+                self.scope = scope;
+                self.functions = functions;
+                result.into()
+            }
+            Err(err) => {
+                // The session's scope went down with the blocking task.
+                self.closed = true;
+                Outcome::Err {
+                    error: ScriptError::new(ErrorKind::Runtime, err.to_string()),
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, code: CloseCode, description: String) -> Message {
+        self.closed = true;
+        Message::Close(Some(CloseReason {
+            code,
+            description: Some(description),
+        }))
+    }
+
+    fn encode(&mut self, message: Message) -> Bytes {
+        let mut out = BytesMut::new();
+        if let Err(err) = self.codec.encode(message, &mut out) {
+            self.closed = true;
+            warn!(error = %err, "REPL session: cannot encode frame");
+        }
+        out.freeze()
+    }
+}
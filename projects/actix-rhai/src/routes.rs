@@ -0,0 +1,142 @@
+use crate::auth::Caller;
+use crate::error::{ErrorKind, ScriptError};
+use crate::meta::Route;
+use crate::metrics::ScriptCall;
+use crate::params::{Args, Input};
+use crate::scripts::{result_type, ScriptHost};
+use actix_web::{
+    http::Method,
+    web::{self, Bytes, Data, Query, ServiceConfig},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use tracing::Span;
+
+/// Paths served by the server itself; scripts can't declare routes under them.
+const RESERVED: &[&str] = &[
+    "run", "batch", "scripts", "admin", "metrics", "repl", "openapi.json", "docs", "healthz", "readyz",
+];
+
+/// A route declared in a script's header, together with the script serving it.
+#[derive(Clone)]
+pub struct Declared {
+    pub route: Route,
+    pub script: String,
+}
+
+/// Collects the routes declared by every loaded script.
+///
+/// Fails with every problem at once if two routes overlap (see [`Route::overlaps`]) or
+/// one would shadow a built-in path. Scripts that don't compile declare nothing.
+pub fn collect(host: &ScriptHost) -> Result<Vec<Declared>, Vec<String>> {
+    let mut declared: Vec<Declared> = Vec::new();
+    let mut problems = Vec::new();
+    for script in host.names() {
+        let Some(Ok(compiled)) = host.script(&script) else {
+            continue;
+        };
+        for route in &compiled.meta.routes {
+            let first = route.path.split('/').nth(1).unwrap_or_default();
+            if RESERVED.contains(&first) {
+                problems.push(format!("`{script}`: route `{} {}` is under a built-in path", route.method, route.path));
+                continue;
+            }
+            if let Some(other) = declared.iter().find(|other| other.route.overlaps(route)) {
+                problems.push(format!(
+                    "`{script}`: route `{} {}` overlaps `{} {}` of `{}`",
+                    route.method, route.path, other.route.method, other.route.path, other.script
+                ));
+                continue;
+            }
+            declared.push(Declared {
+                route: route.clone(),
+                script: script.clone(),
+            });
+        }
+    }
+    if problems.is_empty() { Ok(declared) } else { Err(problems) }
+}
+
+/// Mounts every declared route. Placeholders in the path are passed as named arguments,
+/// along with the query string and, if there is one, a JSON object body.
+pub fn configure(declared: &[Declared], cfg: &mut ServiceConfig) {
+    for Declared { route, script } in declared {
+        let method = Method::from_bytes(route.method.as_bytes()).expect("methods are validated when parsed");
+        let placeholders: Vec<String> = route.placeholders().map(String::from).collect();
+        let script = script.clone();
+        cfg.route(
+            &route.path,
+            web::method(method).to(
+                move |req: HttpRequest, host: Data<ScriptHost>, query: Query<Vec<(String, String)>>, body: Bytes| {
+                    let script = script.clone();
+                    let mut named: Vec<(String, Input)> = placeholders
+                        .iter()
+                        .filter_map(|name| {
+                            let value = req.match_info().get(name)?;
+                            Some((name.clone(), Input::Text(value.to_string())))
+                        })
+                        .collect();
+                    named.extend(query_args(query.into_inner()));
+                    async move {
+                        named.extend(body_args(&body)?);
+                        let args = Args {
+                            positional: Vec::new(),
+                            named,
+                        };
+                        run_script(&req, host, script, args).await
+                    }
+                },
+            ),
+        );
+    }
+}
+
+/// Evaluates the script called `name` with `args` bound to its declared parameters.
+/// Results of pure scripts say in `X-Cache` whether they were memoized.
+///
+/// Evaluation runs on the blocking thread pool so a slow script never stalls a worker.
+pub async fn run_script(
+    req: &HttpRequest,
+    host: Data<ScriptHost>,
+    name: String,
+    args: Args,
+) -> Result<HttpResponse, ScriptError> {
+    req.extensions_mut().insert(ScriptCall(name.clone()));
+    let caller = Caller::of(req);
+    if let Some(caller) = &caller {
+        caller.authorize(&name)?;
+    }
+    let span = Span::current(); // the request's, to carry over to the blocking thread
+    let (result, memoized) = web::block(move || {
+        let _entered = span.enter();
+        let result = host.call_memoized(&name, args);
+        if let Some(caller) = caller {
+            caller.audit(&name, result.as_ref().err());
+        }
+        result
+    })
+    .await
+    .map_err(|err| ScriptError::new(ErrorKind::Runtime, err.to_string()))??;
+
+    // Under the `promote` overflow policy an integer script may hand back a decimal.
+    let mut response = HttpResponse::Ok();
+    response.insert_header(("X-Result-Type", result_type(&result)));
+    if let Some(hit) = memoized {
+        response.insert_header(("X-Cache", if hit { "hit" } else { "miss" }));
+    }
+    Ok(response.body(result.to_string()))
+}
+
+pub fn query_args(query: Vec<(String, String)>) -> Vec<(String, Input)> {
+    query.into_iter().map(|(name, value)| (name, Input::Text(value))).collect()
+}
+
+/// Named arguments from a JSON object body; none if the body is empty.
+pub fn body_args(body: &Bytes) -> Result<Vec<(String, Input)>, ScriptError> {
+    if body.is_empty() {
+        return Ok(Vec::new());
+    }
+    let body: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(body).map_err(|err| {
+        ScriptError::new(ErrorKind::InvalidArgument, format!("request body must be a JSON object: {err}"))
+    })?;
+    Ok(body.into_iter().map(|(name, value)| (name, Input::Json(value))).collect())
+}
@@ -0,0 +1,85 @@
+use crate::arith::{self, Overflow};
+use crate::state;
+use rhai::{Dynamic, Engine};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Resource limits applied to a single script evaluation.
+///
+/// Every limit can be overridden per script in its metadata header (see `meta`);
+/// anything not overridden falls back to the server-wide defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Limits {
+    pub max_operations: u64,
+    pub max_call_levels: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+    pub timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_operations: 1_000_000,
+            max_call_levels: 32,
+            max_string_size: 64 * 1024,
+            max_array_size: 10_000,
+            max_map_size: 10_000,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Engine features turned off for every script unless configured otherwise.
+///
+/// `eval` would let a script compile and run arbitrary code that none of the
+/// metadata (limits, parameters) was declared for.
+pub const DEFAULT_DISABLED_SYMBOLS: &[&str] = &["eval"];
+
+/// Builds an engine enforcing `limits` and the `overflow` policy, with every symbol in
+/// `disabled` turned off.
+///
+/// The wall-clock timeout is not an engine setting; it is checked from the progress
+/// callback against the deadline set by [`with_deadline`] for the current thread.
+pub fn build_engine(limits: &Limits, overflow: Overflow, disabled: &[String]) -> Engine {
+    let mut engine = Engine::new();
+    arith::register(&mut engine, overflow);
+    state::register(&mut engine);
+    engine
+        .set_max_operations(limits.max_operations)
+        .set_max_call_levels(limits.max_call_levels)
+        .set_max_string_size(limits.max_string_size)
+        .set_max_array_size(limits.max_array_size)
+        .set_max_map_size(limits.max_map_size)
+        .on_progress(check_deadline);
+    for symbol in disabled {
+        engine.disable_symbol(symbol);
+    }
+    engine
+}
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Runs `f` with a wall-clock deadline of `timeout` for any script evaluated inside it
+/// on this thread; a script still running at the deadline is terminated.
+pub fn with_deadline<T>(timeout: Duration, f: impl FnOnce() -> T) -> T {
+    let previous = DEADLINE.replace(Some(Instant::now() + timeout));
+    let result = f();
+    DEADLINE.set(previous);
+    result
+}
+
+/// Progress callback: aborts the script once the thread's deadline has passed.
+fn check_deadline(operations: u64) -> Option<Dynamic> {
+    // Reading the clock on every operation would dominate tight loops.
+    if !operations.is_multiple_of(1024) {
+        return None;
+    }
+    match DEADLINE.get() {
+        Some(deadline) if Instant::now() >= deadline => Some("timeout".into()),
+        _ => None,
+    }
+}
@@ -0,0 +1,518 @@
+use crate::error::{ErrorKind, ScriptError};
+use crate::memo::Memo;
+use crate::meta::{self, Meta, Route};
+use crate::metrics::Metrics;
+use crate::modules::LibResolver;
+use crate::params::{self, Args};
+use crate::arith::Overflow;
+use crate::sandbox::{self, Limits};
+use crate::state::{self, StateStore};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rhai::{Dynamic, Engine, EvalAltResult, Module, Position, Scope, Shared, AST};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::{debug, error, info, info_span, warn, Span};
+
+/// A compiled script together with the metadata declared in its header.
+pub struct Compiled {
+    pub ast: AST,
+    pub meta: Meta,
+    /// For a library file, the module it evaluated to once something imported it.
+    pub module: Option<Shared<Module>>,
+    /// Different for every compilation, so memoized results never outlive the code
+    /// that produced them.
+    pub version: u64,
+}
+
+impl Compiled {
+    pub fn new(ast: AST, meta: Meta, module: Option<Shared<Module>>) -> Self {
+        static VERSIONS: AtomicU64 = AtomicU64::new(0);
+        Compiled {
+            ast,
+            meta,
+            module,
+            version: VERSIONS.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Compiled scripts and library files keyed by their canonical path.
+pub type Cache = RwLock<HashMap<PathBuf, Arc<Compiled>>>;
+
+/// Shared Rhai engine plus a cache of compiled scripts keyed by their path.
+///
+/// The engine is built once at startup and handed to every worker through app data,
+/// so a request only has to look up an already compiled `AST` instead of reading and
+/// parsing the `.rhai` file again.
+pub struct ScriptHost {
+    defaults: Meta, // limits and policies for scripts that don't override them
+    disabled_symbols: Vec<String>,
+    state: Arc<StateStore>,
+    engines: RwLock<HashMap<(Limits, Overflow), Arc<Engine>>>, // one engine per distinct configuration
+    cache: Arc<Cache>,
+    resolver: LibResolver,
+    scripts: RwLock<BTreeMap<String, PathBuf>>, // script name (file stem) -> path
+    metrics: Metrics,
+    memo: Memo, // results of pure scripts
+    reloading: AtomicUsize, // watcher events being handled
+    debug: bool,            // log what scripts `print` instead of writing it to stdout
+}
+
+impl ScriptHost {
+    /// Creates a host whose scripts run under `limits` and the `overflow` policy unless
+    /// their header says otherwise, with every symbol in `disabled_symbols` (e.g. `eval`)
+    /// turned off. Scripts keep their persistent state in `state` and import modules
+    /// from `lib_dir`.
+    pub fn new(
+        limits: Limits,
+        overflow: Overflow,
+        disabled_symbols: Vec<String>,
+        state: Arc<StateStore>,
+        lib_dir: impl Into<PathBuf>,
+    ) -> Self {
+        let cache = Arc::new(Cache::default());
+        ScriptHost {
+            defaults: Meta {
+                description: None,
+                params: Vec::new(),
+                returns: None,
+                limits,
+                overflow,
+                sequential: false,
+                pure: false,
+                routes: Vec::new(),
+            },
+            disabled_symbols,
+            state,
+            engines: RwLock::new(HashMap::new()),
+            resolver: LibResolver::new(lib_dir, cache.clone()),
+            cache,
+            scripts: RwLock::new(BTreeMap::new()),
+            metrics: Metrics::default(),
+            memo: Memo::disabled(),
+            reloading: AtomicUsize::new(0),
+            debug: false,
+        }
+    }
+
+    /// Sends what scripts `print` or `debug` to the log, as `debug` events inside the
+    /// span of the call that printed it, instead of to stdout.
+    pub fn with_debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Memoizes the results of scripts declared `pure` in `memo`.
+    pub fn with_memo(mut self, memo: Memo) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    /// Registers and compiles every `*.rhai` file in `dir`, naming each script after
+    /// its file stem (`add.rhai` is served as `add`).
+    ///
+    /// Scripts that fail to compile are still registered: the error is reported here
+    /// and again to every caller until the file is fixed, without affecting the others.
+    pub fn discover(&self, dir: impl AsRef<Path>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(name) = script_name(&path) {
+                self.register(name, &path);
+            }
+        }
+        Ok(())
+    }
+
+    fn register(&self, name: String, path: &Path) {
+        match self.add(name.clone(), path) {
+            Ok(_) => info!(script = name, path = %path.display(), "serving script"),
+            Err(err) => error!(script = name, path = %path.display(), kind = %err.kind, error = %err, "failed to compile"),
+        }
+    }
+
+    /// Makes the script at `path` callable as `name` and compiles it. Like scripts found
+    /// by [`discover`](Self::discover), it stays registered even if it fails to compile.
+    pub fn add(&self, name: String, path: &Path) -> Result<Arc<Compiled>, ScriptError> {
+        let compiled = self.load(path);
+        self.metrics.add_script(&name);
+        self.scripts.write().unwrap().insert(name, key(path));
+        compiled
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn memo(&self) -> &Memo {
+        &self.memo
+    }
+
+    /// Scripts that have never compiled. One whose latest edit is broken still serves
+    /// its last good version and isn't listed.
+    pub fn uncompiled(&self) -> Vec<String> {
+        let scripts = self.scripts.read().unwrap().clone();
+        let cache = self.cache.read().unwrap();
+        scripts.into_iter().filter(|(_, path)| !cache.contains_key(path)).map(|(name, _)| name).collect()
+    }
+
+    /// Whether a change on disk is being recompiled right now.
+    pub fn reloading(&self) -> bool {
+        self.reloading.load(Ordering::Acquire) > 0
+    }
+
+    /// Names of every script that can be run, in sorted order.
+    pub fn names(&self) -> Vec<String> {
+        self.scripts.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Looks up a discovered script by name; `None` if no such script exists.
+    ///
+    /// This is for inspecting a script (its routes, parameters, policies) and doesn't
+    /// count towards the cache metrics; calls are counted once each by
+    /// [`call_memoized`](Self::call_memoized).
+    pub fn script(&self, name: &str) -> Option<Result<Arc<Compiled>, ScriptError>> {
+        self.lookup(name, false)
+    }
+
+    /// Returns the cached compilation of the script called `name`, compiling it if it
+    /// has none yet; with `record`, counts a cache hit or miss.
+    fn lookup(&self, name: &str, record: bool) -> Option<Result<Arc<Compiled>, ScriptError>> {
+        let path = self.scripts.read().unwrap().get(name).cloned()?;
+        let cached = self.cache.read().unwrap().get(&path).cloned();
+        if record {
+            self.metrics.record_cache(cached.is_some());
+        }
+        Some(cached.map_or_else(|| self.load(&path), Ok))
+    }
+
+    /// Whether `version` is still what the script called `name` compiles to.
+    fn is_current(&self, name: &str, version: u64) -> bool {
+        let Some(path) = self.scripts.read().unwrap().get(name).cloned() else {
+            return false;
+        };
+        self.cache.read().unwrap().get(&path).is_some_and(|script| script.version == version)
+    }
+
+    /// Binds `args` to the parameters of the script called `name` and runs it.
+    ///
+    /// This blocks for up to the script's timeout, so call it off the async workers.
+    pub fn call(&self, name: &str, args: Args) -> Result<Dynamic, ScriptError> {
+        self.call_memoized(name, args).map(|(value, _)| value)
+    }
+
+    /// Like [`call`](Self::call), but a script declared `pure` is only run if its memo
+    /// holds no result for the same arguments. Also says whether the result came from
+    /// the memo: `Some(true)` if it did, `Some(false)` if it was computed (and stored),
+    /// and `None` if the script isn't memoized at all.
+    ///
+    /// Each call gets a `script` span recording how it went, logged when it ends.
+    pub fn call_memoized(&self, name: &str, args: Args) -> Result<(Dynamic, Option<bool>), ScriptError> {
+        let span = info_span!(
+            "script",
+            script = name,
+            args = args.positional.len() + args.named.len(),
+            eval_ms = Empty,
+            memo = Empty,
+            outcome = Empty,
+        );
+        let _entered = span.enter();
+        // One lookup per call: the code that runs is the code the memo key's version
+        // belongs to, even if the script is reloaded meanwhile.
+        let result = self.lookup(name, true).ok_or_else(|| ScriptError::not_found(name)).and_then(|script| {
+            let script = script?;
+            let mut scope = params::bind(&script.meta.params, args)?;
+            if !(script.meta.pure && self.memo.enabled()) {
+                return self.run(name, &script, &mut scope).map(|value| (value, None));
+            }
+            // Bound values rather than the raw request, so `"2"` and `2` share an entry.
+            // `Debug` rather than JSON, which writes NaN and both infinities as `null`.
+            let bound: Vec<(&str, &Dynamic)> = scope.iter_raw().map(|(name, _, value)| (name, value)).collect();
+            let key = format!("{bound:?}");
+            if let Some(value) = self.memo.get(name, script.version, &key) {
+                return Ok((value, Some(true)));
+            }
+            let value = self.run(name, &script, &mut scope)?;
+            self.memo.insert(name, script.version, key, value.clone());
+            // A reload while this ran has already invalidated the version; don't leave
+            // the result just stored under it behind.
+            if !self.is_current(name, script.version) {
+                self.memo.invalidate(script.version);
+            }
+            Ok((value, Some(false)))
+        });
+        self.metrics.record_call(name, result.as_ref().err().map(|err| err.kind));
+
+        match &result {
+            Ok((_, memoized)) => {
+                span.record("outcome", "ok");
+                if let Some(hit) = memoized {
+                    span.record("memo", if *hit { "hit" } else { "miss" });
+                }
+                info!("script finished");
+            }
+            Err(err) => {
+                span.record("outcome", tracing::field::display(err.kind));
+                info!(error = %err, "script failed");
+            }
+        }
+        result
+    }
+
+    /// Evaluates `script`, served as `name`, against `scope` within the script's limits.
+    ///
+    /// State written by the script is stored under its name and committed only if the
+    /// whole evaluation succeeds, which includes returning the type the script declares.
+    /// If state the script read changes before then, it runs again within the same timeout.
+    ///
+    /// This blocks for up to the script's timeout, so call it off the async workers.
+    pub fn run(&self, name: &str, script: &Compiled, scope: &mut Scope) -> Result<Dynamic, ScriptError> {
+        let engine = self.engine_for(&script.meta);
+        let start = Instant::now();
+        // One deadline for every attempt; each starts from the scope as it was given.
+        let result = sandbox::with_deadline(script.meta.limits.timeout, || {
+            state::with_txn(&self.state, name, || {
+                let mut attempt = scope.clone();
+                let value = engine.eval_ast_with_scope::<Dynamic>(&mut attempt, &script.ast)?;
+                check_returns(&script.meta, &value)?;
+                Ok((value, attempt))
+            })
+        });
+        let elapsed = start.elapsed();
+        if let Some(metrics) = self.metrics.script(name) {
+            metrics.eval.observe(elapsed);
+        }
+        Span::current().record("eval_ms", elapsed.as_secs_f64() * 1000.0);
+        let (value, attempt) = result?;
+        *scope = attempt;
+        Ok(value)
+    }
+
+    /// Evaluates one line of REPL input against a session's `scope`, with the server-wide
+    /// limits and policies. Functions defined by earlier input are kept in `functions`
+    /// so later input can call them.
+    ///
+    /// State written from the REPL is stored under the name `repl`.
+    ///
+    /// This blocks for up to the default timeout, so call it off the async workers.
+    pub fn eval_repl(&self, scope: &mut Scope<'static>, functions: &mut AST, code: &str) -> Result<Dynamic, ScriptError> {
+        let engine = self.engine_for(&self.defaults);
+        let ast = engine.compile_with_scope(scope, code)?;
+        let input = functions.merge(&ast);
+        let (result, attempt) = sandbox::with_deadline(self.defaults.limits.timeout, || {
+            state::with_txn(&self.state, "repl", || {
+                let mut attempt = scope.clone();
+                let result = engine.eval_ast_with_scope::<Dynamic>(&mut attempt, &input)?;
+                Ok((result, attempt))
+            })
+        })?;
+        *scope = attempt;
+        *functions += ast.clone_functions_only();
+        Ok(result)
+    }
+
+    fn engine_for(&self, meta: &Meta) -> Arc<Engine> {
+        let key = (meta.limits, meta.overflow);
+        if let Some(engine) = self.engines.read().unwrap().get(&key) {
+            return engine.clone();
+        }
+        let mut engines = self.engines.write().unwrap();
+        let engine = engines.entry(key).or_insert_with(|| {
+            let mut engine = sandbox::build_engine(&meta.limits, meta.overflow, &self.disabled_symbols);
+            engine.set_module_resolver(self.resolver.clone());
+            if self.debug {
+                engine
+                    .on_print(|text| debug!(target: "script", text, "print"))
+                    .on_debug(|text, source, pos| debug!(target: "script", text, source, position = %pos, "debug"));
+            }
+            Arc::new(engine)
+        });
+        engine.clone()
+    }
+
+    /// Compiles the script at `path` and stores the result in the cache.
+    ///
+    /// On a compile error the previous `AST` (if any) is left in place, so a broken
+    /// edit never takes down a script that was working before.
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<Arc<Compiled>, ScriptError> {
+        let path = key(path.as_ref());
+        let source = std::fs::read_to_string(&path).map_err(|err| {
+            ScriptError::new(ErrorKind::NotFound, format!("cannot read {}: {err}", path.display()))
+        })?;
+        let meta = meta::parse(&source, &self.defaults)?;
+        // Compile with the engine the script will run on: constant folding calls the
+        // same (possibly overridden) operators. Imports are resolved now and embedded
+        // in the `AST`, so a broken import fails here rather than on some later call.
+        let ast = self
+            .engine_for(&meta)
+            .compile_into_self_contained(&Scope::new(), &source)
+            .map_err(|err| locate_import(err, &source))?;
+        let compiled = Arc::new(Compiled::new(ast, meta, None));
+        if let Some(old) = self.cache.write().unwrap().insert(path, compiled.clone()) {
+            self.memo.invalidate(old.version);
+        }
+        Ok(compiled)
+    }
+
+    /// Watches `dir`, recompiling any cached script that changes on disk and serving
+    /// any new `*.rhai` file dropped into it. Changes to the library directory
+    /// recompile every script, since any of them may import the changed file. While
+    /// that happens, [`reloading`](Self::reloading) says so.
+    ///
+    /// The returned watcher stops watching when dropped, so the caller has to keep it
+    /// alive for as long as hot reloading is wanted.
+    pub fn watch(self: &Arc<Self>, dir: impl AsRef<Path>) -> notify::Result<RecommendedWatcher> {
+        let host = Arc::clone(self);
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let event = match res {
+                Ok(event) => event,
+                Err(err) => return error!(error = %err, "script watcher failed"),
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                return;
+            }
+            host.reloading.fetch_add(1, Ordering::AcqRel);
+            for path in event.paths {
+                if host.resolver.contains(&key(&path)) {
+                    host.reload_lib(&path);
+                } else {
+                    host.reload(&path);
+                }
+            }
+            host.reloading.fetch_sub(1, Ordering::AcqRel);
+        })?;
+        watcher.watch(dir.as_ref(), RecursiveMode::NonRecursive)?;
+        if self.resolver.dir().is_dir() {
+            watcher.watch(self.resolver.dir(), RecursiveMode::Recursive)?;
+        }
+        Ok(watcher)
+    }
+
+    /// Drops every cached library module and recompiles the scripts against the new
+    /// version of `path`; scripts whose imports no longer work keep their last good
+    /// version like any other broken edit.
+    fn reload_lib(&self, path: &Path) {
+        if std::fs::metadata(path).is_ok_and(|meta| meta.len() == 0) {
+            return;
+        }
+        self.cache.write().unwrap().retain(|path, _| !self.resolver.contains(path));
+        let scripts: Vec<PathBuf> = self.scripts.read().unwrap().values().cloned().collect();
+        for script in scripts {
+            if let Err(err) = self.load(&script) {
+                warn!(
+                    path = %script.display(),
+                    changed = %path.display(),
+                    kind = %err.kind,
+                    error = %err,
+                    "failed to recompile after a library changed, keeping last good version"
+                );
+            }
+        }
+        info!(path = %path.display(), "reloaded library");
+    }
+
+    fn reload(&self, path: &Path) {
+        let path = key(path);
+        // Editors and shell redirects truncate before writing; an empty file is almost
+        // always that intermediate state, so wait for the write that follows.
+        if std::fs::metadata(&path).is_ok_and(|meta| meta.len() == 0) {
+            return;
+        }
+        let known = self.scripts.read().unwrap().values().any(|known| *known == path);
+        if !known {
+            if let Some(name) = script_name(&path).filter(|_| path.is_file()) {
+                self.register(name.clone(), &path);
+                if let Some(Ok(script)) = self.script(&name) {
+                    warn_unmounted(&path, &[], &script.meta.routes);
+                }
+            }
+            return;
+        }
+        let routes = self.cache.read().unwrap().get(&path).map(|script| script.meta.routes.clone());
+        match self.load(&path) {
+            Ok(script) => {
+                info!(path = %path.display(), "reloaded script");
+                warn_unmounted(&path, &routes.unwrap_or_default(), &script.meta.routes);
+            }
+            Err(err) => warn!(
+                path = %path.display(),
+                kind = %err.kind,
+                error = %err,
+                "failed to reload, keeping last good version"
+            ),
+        }
+    }
+}
+
+/// Result types a script can declare with `//! returns: <type>`.
+pub const RESULT_TYPES: &[&str] = &["int", "float", "decimal", "string", "bool", "array", "map", "unit"];
+
+/// Name of a result's type as reported to clients.
+pub fn result_type(value: &Dynamic) -> &'static str {
+    match value.type_name() {
+        "i64" => "int",
+        "f64" => "float",
+        "()" => "unit",
+        name => name,
+    }
+}
+
+/// Fails unless `value` has the type the script declares with `//! returns:`. A script
+/// returning `int` may hand back a `decimal` under the `promote` overflow policy.
+fn check_returns(meta: &Meta, value: &Dynamic) -> Result<(), ScriptError> {
+    let Some(declared) = meta.returns else {
+        return Ok(());
+    };
+    let actual = result_type(value);
+    if actual == declared || (declared == "int" && actual == "decimal" && meta.overflow == Overflow::Promote) {
+        return Ok(());
+    }
+    Err(ScriptError::new(
+        ErrorKind::TypeMismatch,
+        format!("script declares it returns `{declared}` but returned `{actual}`"),
+    ))
+}
+
+/// Script name for a path, if it is a `.rhai` file.
+pub fn script_name(path: &Path) -> Option<String> {
+    if path.extension()? != "rhai" {
+        return None;
+    }
+    Some(path.file_stem()?.to_str()?.to_string())
+}
+
+/// Rhai resolves a script's imports without the position of the `import` statement;
+/// point a failed import back at the line that asked for it.
+fn locate_import(mut err: Box<EvalAltResult>, source: &str) -> Box<EvalAltResult> {
+    let (EvalAltResult::ErrorModuleNotFound(module, pos) | EvalAltResult::ErrorInModule(module, _, pos)) = &*err else {
+        return err;
+    };
+    if !pos.is_none() {
+        return err;
+    }
+    let quoted = format!("\"{module}\"");
+    let found = source.lines().enumerate().find_map(|(i, line)| {
+        let column = line.find(&quoted)?;
+        line[..column].trim_start().starts_with("import").then_some((i + 1, column + 1))
+    });
+    if let Some((line, column)) = found {
+        err.set_position(Position::new(line as u16, column as u16));
+    }
+    err
+}
+
+/// Declared routes are only mounted at startup; say so when a reload changes them.
+fn warn_unmounted(path: &Path, before: &[Route], after: &[Route]) {
+    if before != after {
+        warn!(path = %path.display(), "declared routes changed; they take effect after a restart");
+    }
+}
+
+/// Cache key for a script path; canonicalized so that watcher events (which carry
+/// absolute paths) match entries loaded through relative ones.
+pub fn key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,214 @@
+use crate::error::{ErrorKind, ScriptError};
+use redb::backends::InMemoryBackend;
+use redb::{Database, ReadableTable, TableDefinition};
+use rhai::{Dynamic, Engine, EvalAltResult};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// `(namespace, key) -> JSON value`; the namespace is the name of the script that wrote it.
+const TABLE: TableDefinition<(&str, &str), &str> = TableDefinition::new("state");
+
+/// On-disk key-value store behind the `state_get`, `state_set` and `state_delete`
+/// functions, so scripts can keep counters and lookup tables across restarts.
+pub struct StateStore {
+    db: Database,
+}
+
+impl StateStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ScriptError> {
+        Self::init(Database::create(path).map_err(storage_error)?)
+    }
+
+    /// A store that lives only as long as the process, e.g. for tests that must not
+    /// touch the real state.
+    pub fn in_memory() -> Result<Self, ScriptError> {
+        let db = Database::builder().create_with_backend(InMemoryBackend::new()).map_err(storage_error)?;
+        Self::init(db)
+    }
+
+    fn init(db: Database) -> Result<Self, ScriptError> {
+        // Make sure the table exists so that reads never have to special-case it.
+        let txn = db.begin_write().map_err(storage_error)?;
+        txn.open_table(TABLE).map_err(storage_error)?;
+        txn.commit().map_err(storage_error)?;
+        Ok(StateStore { db })
+    }
+
+    /// Every stored value as `namespace -> key -> value`, optionally for one namespace only.
+    pub fn export(&self, namespace: Option<&str>) -> Result<BTreeMap<String, BTreeMap<String, Value>>, ScriptError> {
+        let txn = self.db.begin_read().map_err(storage_error)?;
+        let table = txn.open_table(TABLE).map_err(storage_error)?;
+        let mut export: BTreeMap<String, BTreeMap<String, Value>> = BTreeMap::new();
+        for entry in table.iter().map_err(storage_error)? {
+            let (key, value) = entry.map_err(storage_error)?;
+            let (ns, key) = key.value();
+            if namespace.is_some_and(|namespace| namespace != ns) {
+                continue;
+            }
+            let value = serde_json::from_str(value.value()).unwrap_or(Value::Null);
+            export.entry(ns.to_string()).or_default().insert(key.to_string(), value);
+        }
+        Ok(export)
+    }
+}
+
+/// Most times an evaluation is run before giving up on state that keeps changing under it.
+const MAX_ATTEMPTS: usize = 5;
+
+/// State access for the evaluation running on this thread.
+///
+/// Reads go straight to the store, and the value each key had is remembered; writes are
+/// buffered. Once the script succeeds they are committed in one short write transaction,
+/// and only if every key read still has the value that was read; otherwise the
+/// evaluation is run again. So the store's single writer is never held for longer than a
+/// commit, and a read-modify-write such as a counter increment never loses an update to
+/// a concurrent one.
+struct Txn {
+    store: Arc<StateStore>,
+    namespace: String,
+    reads: BTreeMap<String, Option<String>>,  // key -> JSON value when first read
+    writes: BTreeMap<String, Option<String>>, // key -> JSON value to store, `None` to delete
+}
+
+impl Txn {
+    fn get(&mut self, key: &str) -> Result<Option<String>, ScriptError> {
+        if let Some(value) = self.writes.get(key).or_else(|| self.reads.get(key)) {
+            return Ok(value.clone());
+        }
+        let read = self.store.db.begin_read().map_err(storage_error)?;
+        let table = read.open_table(TABLE).map_err(storage_error)?;
+        let value = table.get((self.namespace.as_str(), key)).map_err(storage_error)?.map(|value| value.value().to_string());
+        self.reads.insert(key.to_string(), value.clone());
+        Ok(value)
+    }
+
+    /// Writes the buffered changes if nothing read has changed since; says whether it did.
+    fn commit(self) -> Result<bool, ScriptError> {
+        if self.writes.is_empty() {
+            return Ok(true);
+        }
+        let write = self.store.db.begin_write().map_err(storage_error)?;
+        {
+            let mut table = write.open_table(TABLE).map_err(storage_error)?;
+            for (key, seen) in &self.reads {
+                let current = table.get((self.namespace.as_str(), key.as_str())).map_err(storage_error)?;
+                if current.as_ref().map(|value| value.value()) != seen.as_deref() {
+                    return Ok(false); // dropping `write` aborts it
+                }
+            }
+            for (key, value) in &self.writes {
+                let key = (self.namespace.as_str(), key.as_str());
+                match value {
+                    Some(value) => table.insert(key, value.as_str()).map(|_| ()),
+                    None => table.remove(key).map(|_| ()),
+                }
+                .map_err(storage_error)?;
+            }
+        }
+        write.commit().map_err(storage_error)?;
+        Ok(true)
+    }
+}
+
+thread_local! {
+    static TXN: RefCell<Option<Txn>> = const { RefCell::new(None) };
+}
+
+/// Makes a [`Txn`] the current one until [`leave`](Self::leave) or, if the evaluation
+/// panics, until dropped; either way the one it replaced comes back.
+struct Current {
+    previous: Option<Option<Txn>>,
+}
+
+impl Current {
+    fn enter(txn: Txn) -> Self {
+        Current {
+            previous: Some(TXN.replace(Some(txn))),
+        }
+    }
+
+    fn leave(mut self) -> Txn {
+        let previous = self.previous.take().expect("left twice");
+        TXN.replace(previous).expect("state transaction vanished")
+    }
+}
+
+impl Drop for Current {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            TXN.set(previous);
+        }
+    }
+}
+
+/// Runs `f` with the state functions bound to `namespace` in `store`, committing all of
+/// its writes atomically if it succeeds and discarding them if it fails.
+///
+/// If state it read was changed by someone else meanwhile, `f` is run again, so it must
+/// not have effects besides its state writes and its result.
+pub fn with_txn<T>(
+    store: &Arc<StateStore>,
+    namespace: &str,
+    mut f: impl FnMut() -> Result<T, ScriptError>,
+) -> Result<T, ScriptError> {
+    for _ in 0..MAX_ATTEMPTS {
+        let current = Current::enter(Txn {
+            store: store.clone(),
+            namespace: namespace.to_string(),
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+        });
+        let result = f();
+        let txn = current.leave();
+        let value = result?;
+        if txn.commit()? {
+            return Ok(value);
+        }
+    }
+    Err(ScriptError::new(
+        ErrorKind::Storage,
+        format!("state of `{namespace}` kept changing during the script; gave up after {MAX_ATTEMPTS} attempts"),
+    ))
+}
+
+fn storage_error(err: impl Into<redb::Error>) -> ScriptError {
+    ScriptError::new(ErrorKind::Storage, format!("state store: {}", err.into()))
+}
+
+/// Registers `state_get(key)`, `state_set(key, value)` and `state_delete(key)`.
+///
+/// Values can be anything JSON can hold; `state_get` returns `()` for a missing key.
+pub fn register(engine: &mut Engine) {
+    engine.register_fn("state_get", |key: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+        with_current(|txn| {
+            let value = txn.get(key).map_err(|err| err.message)?;
+            match value.and_then(|value| serde_json::from_str::<Value>(&value).ok()) {
+                Some(value) => rhai::serde::to_dynamic(value),
+                None => Ok(Dynamic::UNIT),
+            }
+        })
+    });
+    engine.register_fn("state_set", |key: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        let value = serde_json::to_value(&value).map_err(|err| err.to_string())?;
+        with_current(|txn| {
+            txn.writes.insert(key.to_string(), Some(value.to_string()));
+            Ok(())
+        })
+    });
+    engine.register_fn("state_delete", |key: &str| -> Result<(), Box<EvalAltResult>> {
+        with_current(|txn| {
+            txn.writes.insert(key.to_string(), None);
+            Ok(())
+        })
+    });
+}
+
+fn with_current<T>(f: impl FnOnce(&mut Txn) -> Result<T, Box<EvalAltResult>>) -> Result<T, Box<EvalAltResult>> {
+    TXN.with_borrow_mut(|txn| match txn {
+        Some(txn) => f(txn),
+        None => Err("state is not available outside a script run".into()),
+    })
+}
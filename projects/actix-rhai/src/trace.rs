@@ -0,0 +1,71 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing::{info, info_span, Instrument};
+use tracing_subscriber::EnvFilter;
+
+/// Header carrying the request ID, taken from the request if the client sent a usable
+/// one and always set on the response.
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Logs everything as JSON lines on stdout, each event with the spans it happened in.
+/// `RUST_LOG` picks what to log; without it that is `info`, or `debug` in debug mode,
+/// which also includes what scripts `print`.
+pub fn init(debug: bool) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(if debug { "debug" } else { "info" }));
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(false)
+        .with_span_list(true)
+        .init();
+}
+
+/// Middleware giving every request an ID and a `request` span, and logging one access
+/// line per request once the response is ready.
+///
+/// Handlers that run scripts add a `script` span under it, so everything logged while
+/// a script runs (including its `print` output in debug mode) carries the request ID.
+pub async fn request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)))
+        .map(String::from)
+        .unwrap_or_else(new_id);
+    let span = info_span!("request", id = %id, method = %req.method(), path = %req.path());
+    let start = Instant::now();
+
+    let mut response = next.call(req).instrument(span.clone()).await?;
+    if let Ok(id) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID, id);
+    }
+    span.in_scope(|| {
+        info!(
+            status = response.status().as_u16(),
+            elapsed_ms = start.elapsed().as_secs_f64() * 1000.0,
+            "request finished"
+        )
+    });
+    Ok(response)
+}
+
+/// A new request ID: a request counter hashed with a key chosen at random per process.
+fn new_id() -> String {
+    static SEED: OnceLock<RandomState> = OnceLock::new();
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let hash = SEED.get_or_init(RandomState::new).hash_one(NEXT.fetch_add(1, Ordering::Relaxed));
+    format!("{hash:016x}")
+}
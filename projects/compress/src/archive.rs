@@ -0,0 +1,203 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, Builder, EntryType};
+use walkdir::WalkDir;
+
+/// What to do with symbolic links found in the directory being archived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symlinks {
+    /// Store the link itself.
+    #[default]
+    Keep,
+    /// Store what the link points to, descending into linked directories.
+    Follow,
+    /// Leave links out.
+    Skip,
+}
+
+impl Symlinks {
+    pub fn parse(name: &str) -> Option<Symlinks> {
+        match name {
+            "keep" => Some(Symlinks::Keep),
+            "follow" => Some(Symlinks::Follow),
+            "skip" => Some(Symlinks::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// Which paths under the directory go into the archive. Globs are matched against the
+/// path relative to the directory, where `*` stays within one component and `**` spans
+/// any number; they are also matched against the bare file name, so `*.rs` or `target`
+/// work at any depth.
+#[derive(Default)]
+pub struct Filter {
+    include: Option<GlobSet>, // files must match one of these, if there are any
+    exclude: GlobSet,         // files and whole directories matching these are left out
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Filter, String> {
+        Ok(Filter {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(glob_set(include)?)
+            },
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+
+    fn excludes(&self, relative: &Path) -> bool {
+        matches(&self.exclude, relative)
+    }
+
+    fn includes(&self, relative: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| matches(include, relative))
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|err| format!("invalid glob `{pattern}`: {err}"))?;
+        set.add(glob);
+    }
+    set.build().map_err(|err| err.to_string())
+}
+
+fn matches(set: &GlobSet, relative: &Path) -> bool {
+    set.is_match(relative) || relative.file_name().is_some_and(|name| set.is_match(name))
+}
+
+/// Writes everything under `source` that `filter` lets through to `output` as a tar
+/// stream, with paths relative to `source` and with permissions and modification times;
+/// returns the total size of the files stored.
+///
+/// Without include globs every directory is stored, empty ones too. With them, only
+/// the directories leading to an included file are.
+pub fn create<W: Write>(
+    output: W,
+    source: &Path,
+    filter: &Filter,
+    symlinks: Symlinks,
+) -> io::Result<u64> {
+    let mut builder = Builder::new(output);
+    builder.follow_symlinks(symlinks == Symlinks::Follow);
+    let relative = |path: &Path| -> PathBuf {
+        let relative = path.strip_prefix(source);
+        relative
+            .expect("the walk stays under the source")
+            .to_path_buf()
+    };
+    let entries = WalkDir::new(source)
+        .min_depth(1)
+        .follow_links(symlinks == Symlinks::Follow)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !filter.excludes(&relative(entry.path())));
+
+    let mut stored_len = 0;
+    // With include globs, the directories above the current entry not stored yet: they
+    // are once a file in them is.
+    let mut pending: Vec<(usize, PathBuf)> = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let (path, name) = (entry.path(), relative(entry.path()));
+        pending.retain(|(depth, _)| *depth < entry.depth());
+
+        let file_type = entry.file_type();
+        if file_type.is_symlink() && symlinks == Symlinks::Skip {
+            continue;
+        }
+        if file_type.is_dir() {
+            match filter.include {
+                None => builder.append_dir(&name, path)?,
+                Some(_) => pending.push((entry.depth(), path.to_path_buf())),
+            }
+            continue;
+        }
+        if !filter.includes(&name) {
+            continue;
+        }
+        for (_, dir) in pending.drain(..) {
+            builder.append_dir(relative(&dir), &dir)?;
+        }
+        if file_type.is_file() {
+            stored_len += entry.metadata()?.len();
+        }
+        builder.append_path_with_name(path, &name)?;
+    }
+    builder.finish()?;
+    Ok(stored_len)
+}
+
+/// Unpacks the tar stream in `input` into `target`, creating it if needed and restoring
+/// permissions and modification times; returns the total size of the files written.
+///
+/// Entries with absolute paths or `..` components are refused rather than skipped, and
+/// so are entries that would be written through a symlink leading outside `target`.
+pub fn extract<R: Read>(input: R, target: &Path) -> io::Result<u64> {
+    fs::create_dir_all(target)?;
+    let target = target.canonicalize()?;
+    let mut archive = Archive::new(input);
+    archive.set_preserve_permissions(true);
+
+    // Directories go last, deepest first, so that a read-only one doesn't stop its
+    // contents from being written and their contents don't bump its modification time.
+    let mut directories = Vec::new();
+    let mut written_len = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        check_inside(&entry.path()?)?;
+        if entry.header().entry_type() == EntryType::Link
+            && let Some(link) = entry.link_name()?
+        {
+            check_inside(&link)?;
+        }
+
+        match entry.header().entry_type() {
+            EntryType::Directory => directories.push(entry),
+            kind => {
+                if kind.is_file() {
+                    written_len += entry.size();
+                }
+                entry.unpack_in(&target)?;
+            }
+        }
+    }
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut directory in directories {
+        directory.unpack_in(&target)?;
+    }
+    Ok(written_len)
+}
+
+/// Refuses a path from an archive that is absolute or climbs out with `..`.
+fn check_inside(path: &Path) -> io::Result<()> {
+    if path
+        .components()
+        .all(|part| matches!(part, Component::Normal(_) | Component::CurDir))
+    {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "refusing to extract `{}`: it points outside the target directory",
+                path.display()
+            ),
+        ))
+    }
+}
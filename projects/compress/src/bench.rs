@@ -0,0 +1,133 @@
+use crate::codec::Codec;
+use std::env::current_exe;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::process::Command;
+use std::time::Instant;
+
+/// Hidden first argument that makes the binary run a single trial for [`run`]. Every
+/// trial gets a process of its own, so that its peak memory is its own too.
+pub const TRIAL: &str = "__bench-trial";
+
+/// What one trial measured.
+struct Trial {
+    compressed_len: u64,
+    compress_secs: f64,
+    decompress_secs: f64,
+    peak_bytes: Option<u64>, // the trial process's peak resident size, where the OS tells us
+}
+
+/// Compresses `source` in memory at every level of every codec in `codecs` (only at
+/// `level`, if given, for the codecs that have it), decompresses it again, and prints a
+/// table of what each combination costs.
+pub fn run(source: &str, codecs: &[Codec], level: Option<u32>) -> io::Result<()> {
+    let source_len = fs::metadata(source)?.len();
+    let exe = current_exe()?;
+
+    println!("Source len: {source_len:?}");
+    println!(
+        "{:<8} {:>5} {:>7} {:>12} {:>10} {:>12} {:>10}",
+        "Codec", "Level", "Ratio", "Size", "Comp MB/s", "Decomp MB/s", "Peak MiB"
+    );
+    for &codec in codecs {
+        let levels = match level {
+            Some(level) if !codec.levels().contains(&level) => continue,
+            Some(level) => level..=level,
+            None => codec.levels(),
+        };
+        for level in levels {
+            let output = Command::new(&exe)
+                .args([TRIAL, codec.name(), &level.to_string(), source])
+                .output()?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(io::Error::other(format!(
+                    "{codec} level {level}: {}",
+                    stderr.trim()
+                )));
+            }
+            let trial =
+                Trial::parse(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+                    io::Error::other(format!("{codec} level {level}: unexpected trial output"))
+                })?;
+
+            let mb_per_sec = |secs: f64| source_len as f64 / secs / 1e6;
+            let peak = trial.peak_bytes.map_or("-".to_string(), |bytes| {
+                format!("{:.1}", bytes as f64 / (1 << 20) as f64)
+            });
+            println!(
+                "{:<8} {:>5} {:>7.3} {:>12} {:>10.1} {:>12.1} {:>10}",
+                codec.name(),
+                level,
+                source_len as f64 / trial.compressed_len.max(1) as f64,
+                trial.compressed_len,
+                mb_per_sec(trial.compress_secs),
+                mb_per_sec(trial.decompress_secs),
+                peak
+            );
+        }
+    }
+    println!("Ratio: source len / size. Peak MiB: whole trial process, buffers included.");
+    Ok(())
+}
+
+/// Runs one trial (`codec level source`) in this process and prints what it measured
+/// on one line, for [`run`] to read.
+pub fn trial(args: &[String]) -> io::Result<()> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+    let [codec, level, source] = args else {
+        return Err(invalid("a trial takes a codec, a level and a source"));
+    };
+    let codec = Codec::parse(codec).ok_or_else(|| invalid("unknown codec"))?;
+    let level = level
+        .parse()
+        .map_err(|_| invalid("the level must be a number"))?;
+    let input = fs::read(source)?;
+
+    let start = Instant::now();
+    let mut encoder = codec.encoder(level, Vec::new())?;
+    encoder.write_all(&input)?;
+    let compressed = encoder.finish()?;
+    let compress_secs = start.elapsed().as_secs_f64();
+    let compressed_len = compressed.len() as u64;
+
+    let start = Instant::now();
+    let mut decompressed = Vec::with_capacity(input.len());
+    codec
+        .decoder(Cursor::new(compressed))?
+        .read_to_end(&mut decompressed)?;
+    let decompress_secs = start.elapsed().as_secs_f64();
+    if decompressed != input {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "decompressing did not give back the input",
+        ));
+    }
+
+    let peak = peak_bytes().map_or("-".to_string(), |bytes| bytes.to_string());
+    println!("{compressed_len} {compress_secs} {decompress_secs} {peak}");
+    Ok(())
+}
+
+impl Trial {
+    fn parse(line: &str) -> Option<Trial> {
+        let mut fields = line.split_whitespace();
+        Some(Trial {
+            compressed_len: fields.next()?.parse().ok()?,
+            compress_secs: fields.next()?.parse().ok()?,
+            decompress_secs: fields.next()?.parse().ok()?,
+            peak_bytes: fields.next()?.parse().ok(),
+        })
+    }
+}
+
+/// The most memory this process has had resident, as Linux reports it; `None` elsewhere.
+fn peak_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?;
+    Some(kb.trim().parse::<u64>().ok()? * 1024)
+}
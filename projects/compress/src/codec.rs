@@ -0,0 +1,208 @@
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::RangeInclusive;
+
+/// A compression format. Every codec exists in every build, so a file can be recognised
+/// even when the feature needed to read it was left out; using it then fails with a
+/// message saying which feature to enable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zlib,
+    Deflate, // raw deflate: no header, so it can't be detected
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+/// Length of the longest magic number [`Codec::detect`] looks for, xz's.
+const MAGIC_LEN: usize = 6;
+
+/// A compressing writer: everything written to it ends up compressed in the underlying
+/// writer, which [`finish`](Encoder::finish) flushes and hands back.
+pub trait Encoder<W: Write>: Write {
+    fn finish(self: Box<Self>) -> io::Result<W>;
+}
+
+macro_rules! impl_encoder {
+    ($($encoder:ty),* $(,)?) => {$(
+        impl<W: Write> Encoder<W> for $encoder {
+            fn finish(self: Box<Self>) -> io::Result<W> {
+                (*self).finish()
+            }
+        }
+    )*};
+}
+
+impl_encoder!(GzEncoder<W>, ZlibEncoder<W>, DeflateEncoder<W>);
+#[cfg(feature = "bzip2")]
+impl_encoder!(bzip2::write::BzEncoder<W>);
+#[cfg(feature = "xz")]
+impl_encoder!(xz2::write::XzEncoder<W>);
+#[cfg(feature = "zstd")]
+impl_encoder!(zstd::stream::write::Encoder<'static, W>);
+
+impl Codec {
+    pub const ALL: [Codec; 6] = [
+        Codec::Gzip,
+        Codec::Zlib,
+        Codec::Deflate,
+        Codec::Bzip2,
+        Codec::Xz,
+        Codec::Zstd,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zlib => "zlib",
+            Codec::Deflate => "deflate",
+            Codec::Bzip2 => "bzip2",
+            Codec::Xz => "xz",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Codec> {
+        Codec::ALL.into_iter().find(|codec| codec.name() == name)
+    }
+
+    /// The compression levels the codec accepts, from fastest to smallest output.
+    pub fn levels(self) -> RangeInclusive<u32> {
+        match self {
+            Codec::Gzip | Codec::Zlib | Codec::Deflate | Codec::Xz => 0..=9,
+            Codec::Bzip2 => 1..=9,
+            Codec::Zstd => 1..=22,
+        }
+    }
+
+    /// The level used when none is given: each library's own default.
+    pub fn default_level(self) -> u32 {
+        match self {
+            Codec::Zstd => 3,
+            _ => 6,
+        }
+    }
+
+    /// `level` if the codec accepts it.
+    pub fn check_level(self, level: u32) -> Result<u32, String> {
+        let levels = self.levels();
+        if levels.contains(&level) {
+            Ok(level)
+        } else {
+            Err(format!(
+                "{self} levels go from {} to {}, not {level}",
+                levels.start(),
+                levels.end()
+            ))
+        }
+    }
+
+    /// The cargo feature this codec needs, if it isn't always built in.
+    fn feature(self) -> Option<&'static str> {
+        match self {
+            Codec::Gzip | Codec::Zlib | Codec::Deflate => None,
+            Codec::Bzip2 => Some("bzip2"),
+            Codec::Xz => Some("xz"),
+            Codec::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Whether this build can read and write the codec.
+    pub fn available(self) -> bool {
+        match self {
+            Codec::Gzip | Codec::Zlib | Codec::Deflate => true,
+            Codec::Bzip2 => cfg!(feature = "bzip2"),
+            Codec::Xz => cfg!(feature = "xz"),
+            Codec::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Whether `head`, the start of a file, looks like this codec's output.
+    fn matches(self, head: &[u8]) -> bool {
+        match self {
+            Codec::Gzip => head.starts_with(&[0x1f, 0x8b]),
+            // Deflate with a window of at most 32K, and a header checksum that works out.
+            Codec::Zlib => {
+                head.len() >= 2
+                    && head[0] & 0x0f == 8
+                    && head[0] >> 4 <= 7
+                    && u16::from_be_bytes([head[0], head[1]]).is_multiple_of(31)
+            }
+            Codec::Deflate => false,
+            Codec::Bzip2 => head.starts_with(b"BZh"),
+            Codec::Xz => head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Codec::Zstd => head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]),
+        }
+    }
+
+    /// Recognises the codec from the first bytes of `input`, reading until there are
+    /// enough for the longest magic number or the input ends: a pipe may hand them over
+    /// a few at a time. Returns the bytes read too, which the decoder still needs.
+    pub fn detect(input: &mut impl Read) -> io::Result<(Option<Codec>, Vec<u8>)> {
+        let mut head = Vec::with_capacity(MAGIC_LEN);
+        input.take(MAGIC_LEN as u64).read_to_end(&mut head)?;
+        let codec = Codec::ALL.into_iter().find(|codec| codec.matches(&head));
+        Ok((codec, head))
+    }
+
+    /// Wraps `output` so that whatever is written gets compressed with this codec at
+    /// `level`, which must be one of [`levels`](Codec::levels).
+    pub fn encoder<W: Write + 'static>(
+        self,
+        level: u32,
+        output: W,
+    ) -> io::Result<Box<dyn Encoder<W>>> {
+        Ok(match self {
+            Codec::Gzip => Box::new(GzEncoder::new(output, Compression::new(level))),
+            Codec::Zlib => Box::new(ZlibEncoder::new(output, Compression::new(level))),
+            Codec::Deflate => Box::new(DeflateEncoder::new(output, Compression::new(level))),
+            #[cfg(feature = "bzip2")]
+            Codec::Bzip2 => Box::new(bzip2::write::BzEncoder::new(
+                output,
+                bzip2::Compression::new(level),
+            )),
+            #[cfg(feature = "xz")]
+            Codec::Xz => Box::new(xz2::write::XzEncoder::new(output, level)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Box::new(zstd::stream::write::Encoder::new(output, level as i32)?),
+            #[allow(unreachable_patterns)] // every codec is enabled
+            _ => return Err(self.missing()),
+        })
+    }
+
+    /// Wraps `input` so that reading from it yields the decompressed data. Files made by
+    /// concatenating several compressed streams come back whole.
+    pub fn decoder<R: BufRead + 'static>(self, input: R) -> io::Result<Box<dyn Read>> {
+        Ok(match self {
+            Codec::Gzip => Box::new(MultiGzDecoder::new(input)),
+            Codec::Zlib => Box::new(ZlibDecoder::new(input)),
+            Codec::Deflate => Box::new(DeflateDecoder::new(input)),
+            #[cfg(feature = "bzip2")]
+            Codec::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(input)),
+            #[cfg(feature = "xz")]
+            Codec::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(input)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(input)?),
+            #[allow(unreachable_patterns)] // every codec is enabled
+            _ => return Err(self.missing()),
+        })
+    }
+
+    fn missing(self) -> io::Error {
+        let feature = self.feature().unwrap_or(self.name());
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("this build has no {self} support; rebuild with `--features {feature}`"),
+        )
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
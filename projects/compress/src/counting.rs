@@ -0,0 +1,56 @@
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// How many bytes have gone through a [`Counting`] so far. It stays readable after the
+/// wrapper itself has been handed to an encoder or decoder.
+#[derive(Clone, Default)]
+pub struct Count(Rc<Cell<u64>>);
+
+impl Count {
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    fn add(&self, len: usize) {
+        self.0.set(self.0.get() + len as u64);
+    }
+}
+
+/// A reader or writer that counts the bytes read from or written to it, which gives the
+/// sizes to report for streams without metadata, like pipes.
+pub struct Counting<T> {
+    inner: T,
+    count: Count,
+}
+
+impl<T> Counting<T> {
+    pub fn new(inner: T) -> (Self, Count) {
+        let count = Count::default();
+        let counting = Counting {
+            inner,
+            count: count.clone(),
+        };
+        (counting, count)
+    }
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count.add(len);
+        Ok(len)
+    }
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count.add(len);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,27 +1,317 @@
-use flate2::write::GzEncoder;
-use flate2::Compression;
+mod archive;
+mod bench;
+mod codec;
+mod counting;
+
+use archive::{Filter, Symlinks};
+use codec::Codec;
+use counting::{Count, Counting};
 use std::env::args;
 use std::fs::File;
-use std::io::copy;
-use std::io::BufReader;
-use std::time::Instant;
+use std::io::{self, copy, BufReader, BufWriter, Cursor, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: [-c `codec`] [-1..-9|-l `level`] `source` `target` to compress (gzip, level 6 unless given)
+         a directory `source` is archived with tar first, taking these too:
+         [--include `glob`]... [--exclude `glob`]... [--symlinks keep|follow|skip]
+       -d|decompress [-c `codec`] `source` `target` to decompress (codec detected unless given)
+       -x|extract [-c `codec`] `archive` `directory` to unpack an archived directory
+       bench [-c `codec`] [-1..-9|-l `level`] `source` to compare every codec and level in memory
+
+`-` as `source` reads stdin and as `target` writes stdout; the sizes and time go to stderr.
+
+Codecs: gzip, zlib, deflate (raw, never detected), bzip2, xz, zstd
+Levels: 0-9; bzip2 1-9; zstd 1-22 (default 3)";
+
+/// What the command line asks for.
+enum Command {
+    Compress {
+        codec: Codec,
+        level: u32,
+        source: String,
+        target: String,
+        filter: Filter,
+        symlinks: Symlinks,
+    },
+    Decompress {
+        codec: Option<Codec>,
+        source: String,
+        target: String,
+    },
+    Extract {
+        codec: Option<Codec>,
+        source: String,
+        target: String,
+    },
+    Bench {
+        codecs: Vec<Codec>,
+        level: Option<u32>,
+        source: String,
+    },
+}
 
 fn main() {
-    if args().len() != 3 {
-        eprintln!("Usage: `source` `target`")
+    let args: Vec<String> = args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == bench::TRIAL) {
+        if let Err(err) = bench::trial(&args[1..]) {
+            eprintln!("{err}");
+            exit(1);
+        }
+        return;
     }
+    let command = parse_args(args).unwrap_or_else(|problem| {
+        eprintln!("{problem}\n\n{USAGE}");
+        exit(2);
+    });
 
-    let mut input = BufReader::new(File::open(args().nth(1).unwrap()).unwrap()); // reads the contents of the file
-    let output = File::create(args().nth(2).unwrap()).unwrap(); // creates a new empty file handle
-
-    let mut encoder = GzEncoder::new(output, Compression::default()); // Any data written into encoder gets compressed and then written to the output file.
     let start = Instant::now(); // start time
-    copy(&mut input, &mut encoder).unwrap(); // Reads from input (source file) and writes into encoder (gzip writer).
-    let output = encoder.finish().unwrap();
-    println!(
-        "Source len: {:?}",
-        input.get_ref().metadata().unwrap().len()
-    );
-    println!("Target len: {:?}", output.metadata().unwrap().len());
-    println!("Elapsed: {:?}", start.elapsed());
+    let result = match command {
+        Command::Compress {
+            codec,
+            level,
+            source,
+            target,
+            filter,
+            symlinks,
+        } => if Path::new(&source).is_dir() {
+            archive_dir(codec, level, &source, &target, &filter, symlinks)
+        } else if filter.is_empty() && symlinks == Symlinks::Keep {
+            compress_file(codec, level, &source, &target)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "`--include`, `--exclude` and `--symlinks` only apply to directories",
+            ))
+        }
+        .map(|(source_len, target_len)| report(source_len, target_len, start.elapsed())),
+        Command::Decompress {
+            codec,
+            source,
+            target,
+        } => decompress_file(codec, &source, &target)
+            .map(|(source_len, target_len)| report(source_len, target_len, start.elapsed())),
+        Command::Extract {
+            codec,
+            source,
+            target,
+        } => extract_archive(codec, &source, &target)
+            .map(|(source_len, target_len)| report(source_len, target_len, start.elapsed())),
+        Command::Bench {
+            codecs,
+            level,
+            source,
+        } => bench::run(&source, &codecs, level),
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {err}");
+        exit(1);
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let mode =
+        args.next_if(|arg| ["-d", "decompress", "-x", "extract", "bench"].contains(&arg.as_str()));
+    let mut codec = None;
+    let mut level = None;
+    let (mut include, mut exclude, mut symlinks) = (Vec::new(), Vec::new(), None);
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--codec" => {
+                let name = args.next().ok_or("`-c` needs a codec name")?;
+                codec = Some(Codec::parse(&name).ok_or_else(|| format!("unknown codec `{name}`"))?);
+            }
+            "-l" | "--level" => {
+                let value = args.next().ok_or("`-l` needs a level")?;
+                level = Some(
+                    value
+                        .parse::<u32>()
+                        .map_err(|_| format!("`{value}` is not a level"))?,
+                );
+            }
+            "-1" | "-2" | "-3" | "-4" | "-5" | "-6" | "-7" | "-8" | "-9" => {
+                level = arg[1..].parse().ok()
+            }
+            "--include" => include.push(args.next().ok_or("`--include` needs a glob")?),
+            "--exclude" => exclude.push(args.next().ok_or("`--exclude` needs a glob")?),
+            "--symlinks" => {
+                let value = args
+                    .next()
+                    .ok_or("`--symlinks` needs keep, follow or skip")?;
+                symlinks = Some(Symlinks::parse(&value).ok_or_else(|| {
+                    format!("`--symlinks` takes keep, follow or skip, not `{value}`")
+                })?);
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let archiving = !include.is_empty() || !exclude.is_empty() || symlinks.is_some();
+    if mode.is_some() && archiving {
+        return Err("`--include`, `--exclude` and `--symlinks` only apply when compressing".into());
+    }
+    if let Some("-d" | "decompress" | "-x" | "extract") = mode.as_deref() {
+        if level.is_some() {
+            return Err("a level only applies when compressing".to_string());
+        }
+        let [source, target] =
+            <[String; 2]>::try_from(paths).map_err(|_| "expected a `source` and a `target`")?;
+        return Ok(match mode.as_deref() {
+            Some("-x" | "extract") => Command::Extract {
+                codec,
+                source,
+                target,
+            },
+            _ => Command::Decompress {
+                codec,
+                source,
+                target,
+            },
+        });
+    }
+    if mode.is_some() {
+        let [source] = <[String; 1]>::try_from(paths).map_err(|_| "expected a `source`")?;
+        if source == "-" {
+            return Err("bench needs a file to read, not stdin".to_string());
+        }
+        let codecs: Vec<Codec> = match codec {
+            Some(codec) => vec![codec],
+            None => Codec::ALL
+                .into_iter()
+                .filter(|codec| codec.available())
+                .collect(),
+        };
+        if let Some(level) = level
+            && !codecs.iter().any(|codec| codec.levels().contains(&level))
+        {
+            return Err(format!("no codec has level {level}"));
+        }
+        if let (Some(codec), Some(level)) = (codec, level) {
+            codec.check_level(level)?;
+        }
+        return Ok(Command::Bench {
+            codecs,
+            level,
+            source,
+        });
+    }
+    let codec = codec.unwrap_or(Codec::Gzip);
+    let level = match level {
+        Some(level) => codec.check_level(level)?,
+        None => codec.default_level(),
+    };
+    let [source, target] =
+        <[String; 2]>::try_from(paths).map_err(|_| "expected a `source` and a `target`")?;
+    Ok(Command::Compress {
+        codec,
+        level,
+        source,
+        target,
+        filter: Filter::new(&include, &exclude)?,
+        symlinks: symlinks.unwrap_or_default(),
+    })
+}
+
+/// Compresses `source` into `target` with `codec` at `level`; returns both sizes.
+fn compress_file(codec: Codec, level: u32, source: &str, target: &str) -> io::Result<(u64, u64)> {
+    let (mut input, source_len) = open_source(source)?; // reads the contents of the file
+    let (output, target_len) = create_target(target, true)?; // creates a new empty file handle
+
+    let mut encoder = codec.encoder(level, output)?; // Any data written into encoder gets compressed and then written to the output file.
+    copy(&mut input, &mut encoder)?; // Reads from input (source file) and writes into encoder.
+    encoder.finish()?.flush()?;
+    Ok((source_len.get(), target_len.get()))
+}
+
+/// Archives the directory `source` with tar into `target`, compressed with `codec` at
+/// `level`; returns the size of the files archived and of the archive.
+fn archive_dir(
+    codec: Codec,
+    level: u32,
+    source: &str,
+    target: &str,
+    filter: &Filter,
+    symlinks: Symlinks,
+) -> io::Result<(u64, u64)> {
+    let (output, target_len) = create_target(target, true)?;
+    let mut encoder = codec.encoder(level, output)?;
+    let source_len = archive::create(&mut encoder, Path::new(source), filter, symlinks)?;
+    encoder.finish()?.flush()?;
+    Ok((source_len, target_len.get()))
+}
+
+/// Restores the original contents of the compressed file `source` into `target`,
+/// detecting the codec from the file's first bytes unless one is given; returns both
+/// sizes.
+fn decompress_file(codec: Option<Codec>, source: &str, target: &str) -> io::Result<(u64, u64)> {
+    let (mut decoder, source_len) = open_compressed(codec, source)?;
+    let (mut output, target_len) = create_target(target, false)?;
+    copy(&mut decoder, &mut output)?;
+    output.flush()?;
+    Ok((source_len.get(), target_len.get()))
+}
+
+/// Unpacks the compressed archive `source` into the directory `target`; returns the
+/// size of the archive and of the files extracted.
+fn extract_archive(codec: Option<Codec>, source: &str, target: &str) -> io::Result<(u64, u64)> {
+    let (decoder, source_len) = open_compressed(codec, source)?;
+    let target_len = archive::extract(decoder, Path::new(target))?;
+    Ok((source_len.get(), target_len))
+}
+
+/// Opens the compressed file `source` for reading its original contents, detecting the
+/// codec unless one is given.
+fn open_compressed(codec: Option<Codec>, source: &str) -> io::Result<(Box<dyn Read>, Count)> {
+    let (mut input, source_len) = open_source(source)?;
+    let (codec, head) = match codec {
+        Some(codec) => (codec, Vec::new()),
+        None => match Codec::detect(&mut input)? {
+            (Some(codec), head) => (codec, head),
+            (None, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{source} is not in a format this tool can detect"),
+                ))
+            }
+        },
+    };
+    // The bytes detection read go first, then the rest of the input.
+    let input = BufReader::new(Cursor::new(head).chain(input));
+    Ok((codec.decoder(input)?, source_len))
+}
+
+/// Opens `source` for reading, or stdin for `-`, counting the bytes read.
+fn open_source(source: &str) -> io::Result<(Counting<Box<dyn Read>>, Count)> {
+    let input: Box<dyn Read> = match source {
+        "-" => Box::new(io::stdin().lock()),
+        path => Box::new(File::open(path)?),
+    };
+    Ok(Counting::new(input))
+}
+
+/// Creates `target` for writing, or buffers stdout for `-`, counting the bytes written.
+/// Compressed data is not written to a terminal.
+fn create_target(target: &str, compressed: bool) -> io::Result<(Counting<Box<dyn Write>>, Count)> {
+    let output: Box<dyn Write> = match target {
+        "-" if compressed && io::stdout().is_terminal() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "refusing to write compressed data to a terminal",
+            ));
+        }
+        "-" => Box::new(BufWriter::new(io::stdout().lock())),
+        path => Box::new(File::create(path)?),
+    };
+    Ok(Counting::new(output))
+}
+
+/// Prints the sizes and time to stderr, so that stdout can carry the data.
+fn report(source_len: u64, target_len: u64, elapsed: Duration) {
+    eprintln!("Source len: {source_len:?}");
+    eprintln!("Target len: {target_len:?}");
+    eprintln!("Elapsed: {elapsed:?}");
 }